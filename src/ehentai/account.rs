@@ -0,0 +1,96 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use hyper::{http, Uri};
+
+use super::client::Client;
+use super::parser;
+use crate::Error;
+
+// how many images can still be loaded before the site
+// starts answering with the "509" image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageLimit {
+    pub used: u64,
+    pub max: u64,
+    // GP it takes to bring `used` back to zero, if the site offers it
+    pub reset_cost: Option<u64>,
+}
+
+impl ImageLimit {
+    pub fn remaining(&self) -> u64 {
+        self.max.saturating_sub(self.used)
+    }
+}
+
+// what the account pages tell about the account
+#[derive(Debug, Clone)]
+pub struct AccountStatus {
+    pub image_limit: ImageLimit,
+
+    // the rest is only known to members
+    pub gp: Option<u64>,
+    pub credits: Option<u64>,
+    // names of the acquired perks, e.g. "Bronze Star"
+    pub hath_perks: Vec<String>,
+}
+
+// the account pages are on e-hentai.org even for exhentai
+fn uri(client: &Client, page: &str) -> Result<Uri, http::Error> {
+    let base = client.home();
+    let path = base.path().trim_end_matches('/');
+
+    Uri::builder()
+        .scheme(base.scheme_str().unwrap_or("https"))
+        .authority(base.authority().map_or("e-hentai.org", |auth| auth.as_str()))
+        .path_and_query(format!("{}/{}", path, page))
+        .build()
+}
+
+// what the home page says, which guests can see too
+pub(super) async fn image_limit(client: &Client) -> Result<ImageLimit, Error> {
+    const HOME: &str = "home.php";
+
+    let doc = client.get_html(uri(client, HOME)?).await?;
+
+    match parser::image_limit(&doc) {
+        Ok(limit) => Ok(limit),
+        Err(_) if parser::login_required(&doc) => Err(Error::Unauthorized),
+        Err(err) => Err(err.at(HOME).into()),
+    }
+}
+
+// a Document can't be held across an await, hence the blocks
+pub(super) async fn status(client: &Client) -> Result<AccountStatus, Error> {
+    let image_limit = image_limit(client).await?;
+
+    if !client.logged_in() {
+        return Ok(AccountStatus {
+            image_limit,
+            gp: None,
+            credits: None,
+            hath_perks: Vec::new(),
+        });
+    }
+
+    // the home page doesn't show the balances
+    let (gp, credits) = {
+        const EXCHANGE: &str = "exchange.php?t=gp";
+        let doc = client.get_html(uri(client, EXCHANGE)?).await?;
+        parser::balances(&doc).map_err(|err| err.at(EXCHANGE))?
+    };
+
+    let hath_perks = {
+        const PERKS: &str = "hathperks.php";
+        let doc = client.get_html(uri(client, PERKS)?).await?;
+        parser::hath_perks(&doc).map_err(|err| err.at(PERKS))?
+    };
+
+    Ok(AccountStatus {
+        image_limit,
+        gp: Some(gp),
+        credits: Some(credits),
+        hath_perks,
+    })
+}
@@ -3,13 +3,21 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::slice;
+use std::path::Path;
 use std::sync::Arc;
+use std::future::Future;
+use hyper::Uri;
+use select::document::Document;
+use tokio::io::AsyncWrite;
 
 use super::tag::{ArticleKind, TagMap};
-use super::client::Client;
+use super::client::{Client, FullImage};
+use super::retry::RetryPolicy;
+use super::stream::ImageStream;
+use super::integrity;
+use super::account;
 use super::parser;
-
-type ErrorBox = Box<dyn std::error::Error>;
+use crate::Error;
 
 #[derive(Debug, Clone)]
 pub struct DraftMeta {
@@ -26,13 +34,15 @@ pub struct DraftMeta {
 pub struct Draft {
     client: Arc<Client>,
     meta: DraftMeta,
+    bypass_cache: bool,
 }
 
 impl Draft {
-    pub(super) fn new(client: Arc<Client>, meta: DraftMeta) -> Self {
+    pub(super) fn new(client: Arc<Client>, meta: DraftMeta, bypass_cache: bool) -> Self {
         Self {
             client,
             meta,
+            bypass_cache,
         }
     }
 
@@ -40,12 +50,15 @@ impl Draft {
         &self.meta
     }
 
-    pub async fn load_thumb(&self) -> Result<Vec<u8>, ErrorBox> {
-        self.client.get_image(self.meta.thumb.parse()?).await
+    pub async fn load_thumb(&self) -> Result<Vec<u8>, Error> {
+        // thumbnails in a list are shown on the front page (or a search)
+        let referer = self.client.base().to_string();
+        self.client.get_image(self.meta.thumb.parse()?, &referer).await
     }
 
-    pub async fn load(self) -> Result<Article, ErrorBox> {
-        Article::new(self.client, self.meta.path).await
+    // the article bypasses the cache if the list it came from did
+    pub async fn load(self) -> Result<Article, Error> {
+        Article::new(self.client, self.meta.path, self.bypass_cache).await
     }
 }
 
@@ -74,6 +87,51 @@ pub struct ArticleMeta {
     pub tags: TagMap,
 }
 
+// where image_source() found an image
+struct Source {
+    path: String,
+    // the reload key, see load_image_with()
+    nl: Option<String>,
+    // what the image is checked against, if it can be
+    hash: Option<String>,
+}
+
+// what an image page (the one at "/s/...") tells about its image
+#[derive(Debug, Clone)]
+pub struct ImagePage {
+    pub path: String,
+    // position in the gallery, counting from 0 like load_image()
+    pub index: usize,
+
+    // the image as shown, which may be resampled
+    pub src: String,
+    pub filename: String,
+    pub width: u32,
+    pub height: u32,
+    pub file_size: String,
+
+    // "fullimg" link to the original, if the shown one is resampled
+    pub original: Option<String>,
+    // key for asking another image server, if this one fails
+    pub nl: Option<String>,
+
+    pub prev: String,
+    pub next: String,
+}
+
+// which image load_original() goes for, when the shown one is resampled;
+// originals are free up to the image limits of the account, then cost GP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginalPolicy {
+    // the resampled one, as load_image() does
+    Resampled,
+    // the original, falling back to the resampled one if it'd cost GP
+    // (i.e. the image limit is used up) or if we aren't logged in
+    OriginalWhenFree,
+    // the original or nothing
+    Original,
+}
+
 #[derive(Debug)]
 pub(super) struct Vote {
     pub(super) score: i64,
@@ -94,6 +152,28 @@ pub struct Comment {
 }
 
 impl Comment {
+    pub fn posted(&self) -> &str {
+        &self.posted
+    }
+
+    // when it was last edited, if ever
+    pub fn edited(&self) -> Option<&str> {
+        self.edited.as_deref()
+    }
+
+    pub fn writer(&self) -> &str {
+        &self.writer
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    // uploader comments can't be voted on
+    pub fn is_uploader(&self) -> bool {
+        self.vote.is_none()
+    }
+
     pub fn score(&self) -> Option<i64> {
         self.vote.as_ref().map(|v| v.score)
     }
@@ -113,20 +193,35 @@ pub struct Article {
     meta: ArticleMeta,
     links: Vec<String>,
     comments: Vec<Comment>,
+    bypass_cache: bool,
 }
 
 impl Article {
-    pub(super) async fn new(client: Arc<Client>, path: String)
-        -> Result<Article, ErrorBox> {
-        let doc = client.get_html(path.parse()?).await?;
+    pub(super) async fn new(client: Arc<Client>, path: String, bypass_cache: bool)
+        -> Result<Article, Error> {
+        let doc = client.load_html(path.parse()?, client.retry(), bypass_cache).await?;
+        let links = parser::image_list(&doc).map_err(|err| err.at(&path))?;
+        let comments = parser::comments(&doc).map_err(|err| err.at(&path))?;
+
         Ok(Self {
             client,
             meta: parser::article(&doc, path)?,
-            links: parser::image_list(&doc)?,
-            comments: parser::comments(&doc)?,
+            links,
+            comments,
+            bypass_cache,
         })
     }
 
+    // always fetch the pages from now on, even if they're in the cache
+    pub fn bypass_cache(mut self, bypass: bool) -> Self {
+        self.bypass_cache = bypass;
+        self
+    }
+
+    async fn load_html(&self, path: &str, retry: &RetryPolicy) -> Result<Document, Error> {
+        self.client.load_html(path.parse()?, retry, self.bypass_cache).await
+    }
+
     pub fn meta(&self) -> &ArticleMeta {
         &self.meta
     }
@@ -136,49 +231,240 @@ impl Article {
         self.comments.iter()
     }
 
-    pub async fn load_thumb(&self) -> Result<Vec<u8>, ErrorBox> {
-        self.client.get_image(self.meta.thumb.parse()?).await
+    pub async fn load_thumb(&self) -> Result<Vec<u8>, Error> {
+        self.client.get_image(self.meta.thumb.parse()?, &self.meta.path).await
     }
 
-    pub async fn load_image_list(&mut self) -> Result<(), ErrorBox> {
+    pub async fn load_image_list(&mut self) -> Result<(), Error> {
         if self.links.len() == self.meta().length {
             return Ok(());
         }
 
         const IMAGES_PER_PAGE: usize = 40;
-        let page_len = 1 + (self.meta.length - 1) / IMAGES_PER_PAGE;
+        let page_len = (self.meta.length + IMAGES_PER_PAGE - 1) / IMAGES_PER_PAGE;
 
         // start from 1 because we've already parsed page 0
         for i in 1..page_len {
-            let doc = self.client.get_html(
-                format!("{}?p={}", self.meta.path, i).parse()?
-            ).await?;
+            let path = format!("{}?p={}", self.meta.path, i);
+            let doc = self.load_html(&path, self.client.retry()).await?;
 
-            self.links.extend(parser::image_list(&doc)?);
+            self.links.extend(parser::image_list(&doc).map_err(|err| err.at(&path))?);
         }
 
         Ok(())
     }
 
-    pub async fn load_image(&self, index: usize) -> Result<Vec<u8>, ErrorBox> {
-        // is this really the best?
-        if index >= self.links.len() {
-            panic!(":P"); // TODO
+    // everything the page of an image tells, e.g. its original filename
+    pub async fn image_page(&self, index: usize) -> Result<ImagePage, Error> {
+        let page = self.image_link(index)?;
+        let doc = self.load_html(page, self.client.retry()).await?;
+
+        Ok(parser::image_page(&doc, page.to_owned())?)
+    }
+
+    pub async fn load_image(&self, index: usize) -> Result<Vec<u8>, Error> {
+        self.load_image_with(index, self.client.retry()).await
+    }
+
+    // same as load_image(), but retry as the given policy says;
+    // bulk downloads may want to be more persistent than the default
+    //
+    // a failing image server is given up on for another one, which the
+    // image page picks when asked with its reload key; see failover()
+    // of ExplorerBuilder. so is one sending a corrupted image, if it's
+    // shown in its original form and can thus be checked
+    pub async fn load_image_with(&self, index: usize, retry: &RetryPolicy)
+        -> Result<Vec<u8>, Error> {
+        self.failing_over(index, retry, |source, page| async move {
+            self.fetch_image(source.path.parse()?, &page, source.hash.as_deref(), retry).await
+        }).await
+    }
+
+    // run `attempt` with the image page and what it shows,
+    // going to another image server each time it fails
+    async fn failing_over<T, F, Fut>(&self, index: usize, retry: &RetryPolicy, mut attempt: F)
+        -> Result<T, Error>
+    where
+        F: FnMut(Source, String) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut page = self.image_link(index)?.to_owned();
+        let mut source = self.image_source(&page, retry).await?;
+        let mut hops = 0;
+
+        loop {
+            let nl = source.nl.clone();
+
+            let err = match attempt(source, page.clone()).await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            let next = match nl {
+                Some(key) if hops < self.client.failover() && fails_over(&err) => key,
+                _ => return Err(err),
+            };
+
+            // as the site does, keys pile up in the query
+            let sep = if page.contains('?') { '&' } else { '?' };
+            page = format!("{}{}nl={}", page, sep, next);
+
+            source = self.image_source(&page, retry).await?;
+            hops += 1;
+        }
+    }
+
+    // get the image, checking it against `hash` if we know it; a corrupted
+    // one is retried as it's most likely been damaged on the way
+    async fn fetch_image(&self, dest: Uri, page: &str, hash: Option<&str>, retry: &RetryPolicy)
+        -> Result<Vec<u8>, Error> {
+        retry.run(|| async {
+            let data = self.client.get_image_with(dest.clone(), page, &RetryPolicy::never()).await?;
+
+            if let Some(hash) = hash {
+                integrity::verify(hash, &data)?;
+            }
+
+            Ok(data)
+        }).await
+    }
+
+    // the image in its original resolution, which only logged in
+    // accounts can download; the shown one if it isn't resampled
+    pub async fn load_original(&self, index: usize, policy: OriginalPolicy)
+        -> Result<Vec<u8>, Error> {
+        if policy == OriginalPolicy::Resampled {
+            return self.load_image(index).await;
+        }
+
+        let page = self.image_page(index).await?;
+        let retry = self.client.retry();
+        let hash = integrity::expected_hash(&page.path);
+
+        let link = match page.original {
+            Some(link) if self.client.logged_in() => link,
+            Some(_) if policy == OriginalPolicy::Original => return Err(Error::Unauthorized),
+            _ => return self.load_image(index).await,
+        };
+
+        // past the image limit the site charges GP without asking,
+        // so look before following the link
+        if policy == OriginalPolicy::OriginalWhenFree
+            && account::image_limit(&self.client).await?.remaining() == 0 {
+            return self.load_image(index).await;
+        }
+
+        let res = match self.client.full_image(link.parse()?, &page.path, retry).await? {
+            FullImage::Redirect(dest) => {
+                self.fetch_image(dest, &page.path, hash.as_deref(), retry).await
+            },
+            FullImage::Image(data) => match &hash {
+                Some(hash) => integrity::verify(hash, &data).map(|_| data),
+                None => Ok(data),
+            },
+            FullImage::Notice(text) => Err(parser::original_notice(&text)),
+        };
+
+        match res {
+            Err(Error::GpRequired) | Err(Error::QuotaExceeded)
+                if policy == OriginalPolicy::OriginalWhenFree => {
+                self.load_image(index).await
+            },
+            res => res,
         }
+    }
+
+    // start downloading an image without buffering it in memory;
+    // see ImageStream::content_length() for progress reporting
+    //
+    // an image which can be checked is checked only once it's all there,
+    // so the stream may end with Error::Integrity after it has handed out
+    // every chunk; whatever was made of them should be thrown away then.
+    // there's no failover either, as the chunks can't be taken back
+    pub async fn image_stream(&self, index: usize) -> Result<ImageStream, Error> {
+        let retry = self.client.retry();
+        let (page, source) = self.image_path(index, retry).await?;
+        let stream = self.client.stream_image(source.path.parse()?, page, retry).await?;
+
+        Ok(match &source.hash {
+            Some(hash) => stream.verify(hash),
+            None => stream,
+        })
+    }
 
-        let path = parser::image(
-            &self.client.get_html(self.links[index].parse()?).await?
-        )?;
+    // download an image straight into `writer` (e.g. a tokio::fs::File),
+    // returning how many bytes were written; on Error::Integrity the
+    // writer has got a corrupted image, see image_stream().
+    // download_image() takes care of that, and fails over
+    pub async fn save_image<W>(&self, index: usize, writer: W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.image_stream(index).await?.write_to(writer).await
+    }
 
-        let data = self.client.get_image(path.parse()?).await?;
-        Ok(data)
+    // download an image into the file at `path`; an interrupted download
+    // leaves "<path>.part" behind, which is continued next time. a corrupted
+    // one never makes it to `path`, and another server is tried instead
+    pub async fn download_image(&self, index: usize, path: impl AsRef<Path>)
+        -> Result<u64, Error> {
+        let retry = self.client.retry();
+        let path = path.as_ref();
+
+        self.failing_over(index, retry, |source, page| async move {
+            let dest = source.path.parse()?;
+            self.client.download(dest, &page, path, source.hash.as_deref(), retry).await
+        }).await
     }
 
-    pub async fn load_all_comments(&mut self) -> Result<(), ErrorBox> {
-        let path = format!("{}?hc=1", self.meta.path).parse()?;
-        let doc = self.client.get_html(path).await?;
-        self.comments = parser::comments(&doc)?;
+    // get the link to the image page and where the image itself is
+    async fn image_path(&self, index: usize, retry: &RetryPolicy)
+        -> Result<(&str, Source), Error> {
+        let page = self.image_link(index)?;
+        let source = self.image_source(page, retry).await?;
+
+        Ok((page, source))
+    }
+
+    fn image_link(&self, index: usize) -> Result<&str, Error> {
+        self.links.get(index).map(String::as_str).ok_or_else(|| Error::InvalidInput(format!(
+            "image index {} is out of range (loaded {} images)",
+            index, self.links.len()
+        )))
+    }
+
+    // the image shown in the page, and the key to ask for another server
+    async fn image_source(&self, page: &str, retry: &RetryPolicy) -> Result<Source, Error> {
+        let doc = self.load_html(page, retry).await?;
+        let path = parser::image(&doc).map_err(|err| err.at(page))?;
+
+        // the hash in the link is of the original, not of a resampled copy
+        let hash = match parser::original(&doc) {
+            Some(_) => None,
+            None => integrity::expected_hash(page),
+        };
+
+        Ok(Source {
+            path,
+            nl: parser::reload_key(&doc),
+            hash,
+        })
+    }
+
+    pub async fn load_all_comments(&mut self) -> Result<(), Error> {
+        let path = format!("{}?hc=1", self.meta.path);
+        let doc = self.load_html(&path, self.client.retry()).await?;
+        self.comments = parser::comments(&doc).map_err(|err| err.at(&path))?;
 
         Ok(())
     }
 }
+
+// whether another image server might do better
+fn fails_over(err: &Error) -> bool {
+    matches!(
+        err.inner(),
+        Error::Network(_) | Error::Timeout | Error::Status(_) | Error::NotFound
+            | Error::Integrity { .. }
+    )
+}
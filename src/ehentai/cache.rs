@@ -0,0 +1,309 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::Uri;
+use tokio::fs;
+
+use super::record::{normalize, fnv1a};
+
+// pages worth keeping, each going stale at its own pace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    // a list of galleries, which changes as soon as something is uploaded
+    Search,
+    // a gallery, along with its list of images and comments
+    Gallery,
+    // the page showing a single image; the links in it are signed
+    // and soon expire, so it isn't kept unless asked to
+    Image,
+}
+
+impl PageKind {
+    // None for pages we'd rather always fetch, e.g. the account pages
+    pub(super) fn of(uri: &Uri) -> Option<Self> {
+        let path = uri.path();
+        let query = uri.query().unwrap_or_default();
+
+        if path.contains("/g/") {
+            Some(PageKind::Gallery)
+        } else if path.contains("/s/") {
+            Some(PageKind::Image)
+        } else if path == "/"
+            || query.contains("f_search=")
+            || path.starts_with("/tag/")
+            || path.starts_with("/uploader/") {
+            Some(PageKind::Search)
+        } else {
+            None
+        }
+    }
+}
+
+// where to keep pages on disk, for how long, and how much of them
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    dir: PathBuf,
+    search: Duration,
+    gallery: Duration,
+    image: Duration,
+    max_size: u64,
+}
+
+impl CachePolicy {
+    // by default search results are kept for 10 minutes and galleries
+    // for an hour, up to 256 MiB in total; image pages aren't kept
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            search: Duration::from_secs(10 * 60),
+            gallery: Duration::from_secs(60 * 60),
+            image: Duration::ZERO,
+            max_size: 256 * 1024 * 1024,
+        }
+    }
+
+    // a zero ttl stops caching the kind altogether
+    pub fn ttl(mut self, kind: PageKind, ttl: Duration) -> Self {
+        match kind {
+            PageKind::Search => self.search = ttl,
+            PageKind::Gallery => self.gallery = ttl,
+            PageKind::Image => self.image = ttl,
+        }
+
+        self
+    }
+
+    // the least recently used pages are thrown away beyond this
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    fn ttl_of(&self, kind: PageKind) -> Duration {
+        match kind {
+            PageKind::Search => self.search,
+            PageKind::Gallery => self.gallery,
+            PageKind::Image => self.image,
+        }
+    }
+}
+
+// a page as it was received, to be decoded again when it's used
+pub(super) struct Cached {
+    pub(super) body: Vec<u8>,
+    pub(super) content_type: Option<String>,
+}
+
+struct Entry {
+    size: u64,
+    // when it was last used, on a clock which ticks on every use
+    used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    total: u64,
+    clock: u64,
+}
+
+impl Index {
+    fn touch(&mut self, name: &str) {
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.used = self.clock;
+        }
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        self.clock += 1;
+        self.remove(&name);
+        self.total += size;
+        self.entries.insert(name, Entry { size, used: self.clock });
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(old) = self.entries.remove(name) {
+            self.total -= old.size;
+        }
+    }
+
+    // the least recently used entries to drop to get within `max`
+    fn evict(&mut self, max: u64) -> Vec<String> {
+        let mut victims = Vec::new();
+
+        while self.total > max {
+            let name = match self.entries.iter().min_by_key(|(_, entry)| entry.used) {
+                Some((name, _)) => name.clone(),
+                None => break,
+            };
+
+            self.remove(&name);
+            victims.push(name);
+        }
+
+        victims
+    }
+}
+
+// the cache is only there to save requests; if it can't be read or
+// written for some reason, we just go to the network as usual
+pub(super) struct Cache {
+    policy: CachePolicy,
+    index: Mutex<Index>,
+}
+
+impl Cache {
+    // pick up what previous sessions left in the directory;
+    // the ones written last are taken as the most recently used
+    pub(super) fn open(policy: CachePolicy) -> Self {
+        let mut found = std::fs::read_dir(&policy.dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                let name = name.strip_suffix(".page")?.to_owned();
+                let meta = entry.metadata().ok()?;
+
+                Some((meta.modified().unwrap_or(UNIX_EPOCH), name, meta.len()))
+            })
+            .collect::<Vec<_>>();
+
+        found.sort_unstable();
+
+        let mut index = Index::default();
+
+        for (_, name, size) in found {
+            index.insert(name, size);
+        }
+
+        let cache = Self {
+            policy,
+            index: Mutex::new(index),
+        };
+
+        // the cap may be lower than it was last time
+        let victims = cache.index.lock().unwrap().evict(cache.policy.max_size);
+        cache.remove(victims);
+
+        cache
+    }
+
+    // only when opening; put() removes them without blocking
+    fn remove(&self, victims: Vec<String>) {
+        for victim in victims {
+            let _ = std::fs::remove_file(self.path(&victim));
+        }
+    }
+
+    // pages are told apart by the cookies they're requested with too,
+    // as the site shows different things to different accounts
+    pub(super) fn key(uri: &Uri, cookies: Option<&str>) -> String {
+        let key = format!("{} {}", normalize(uri), cookies.unwrap_or_default());
+        format!("{:016x}", fnv1a(&key))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.policy.dir.join(format!("{}.page", name))
+    }
+
+    // the page if we have a fresh enough copy of it
+    pub(super) async fn get(&self, name: &str, uri: &Uri, kind: PageKind) -> Option<Cached> {
+        let ttl = self.policy.ttl_of(kind);
+
+        if ttl.is_zero() || !self.index.lock().unwrap().entries.contains_key(name) {
+            return None;
+        }
+
+        let data = fs::read(self.path(name)).await.ok()?;
+
+        // the file starts with three lines: the url, when it was
+        // fetched and its content type, which may be empty
+        let mut parts = data.splitn(4, |&byte| byte == b'\n');
+        let url = parts.next()?;
+        let fetched = std::str::from_utf8(parts.next()?).ok()?.parse::<u64>().ok()?;
+        let content_type = String::from_utf8(parts.next()?.to_vec()).ok()?;
+        let body = parts.next()?.to_vec();
+
+        // a (very unlikely) collision of hashes
+        if url != normalize(uri).as_bytes() {
+            return None;
+        }
+
+        let fetched = UNIX_EPOCH.checked_add(Duration::from_secs(fetched))?;
+        let age = SystemTime::now().duration_since(fetched).unwrap_or(Duration::ZERO);
+
+        if age >= ttl {
+            return None;
+        }
+
+        self.index.lock().unwrap().touch(name);
+
+        Some(Cached {
+            body,
+            content_type: Some(content_type).filter(|value| !value.is_empty()),
+        })
+    }
+
+    pub(super) async fn put(
+        &self,
+        name: &str,
+        uri: &Uri,
+        kind: PageKind,
+        body: &[u8],
+        content_type: Option<&str>
+    ) {
+        if self.policy.ttl_of(kind).is_zero() {
+            return;
+        }
+
+        let fetched = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        let mut data = format!(
+            "{}\n{}\n{}\n",
+            normalize(uri), fetched, content_type.unwrap_or_default()
+        ).into_bytes();
+        data.extend_from_slice(body);
+
+        // write it aside first, so nobody reads a half-written page
+        let path = self.path(name);
+        let temp = path.with_extension("tmp");
+
+        let written = async {
+            fs::create_dir_all(&self.policy.dir).await?;
+            fs::write(&temp, &data).await?;
+            fs::rename(&temp, &path).await
+        }.await;
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temp).await;
+            return;
+        }
+
+        let victims = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(name.to_owned(), data.len() as u64);
+            index.evict(self.policy.max_size)
+        };
+
+        for victim in victims {
+            let _ = fs::remove_file(self.path(&victim)).await;
+        }
+    }
+
+    // take back a page which turned out to be of no use
+    pub(super) async fn forget(&self, name: &str) {
+        self.index.lock().unwrap().remove(name);
+        let _ = fs::remove_file(self.path(name)).await;
+    }
+}
@@ -0,0 +1,72 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use encoding_rs::{Encoding, UTF_8};
+
+pub(super) struct Decoded {
+    pub(super) text: String,
+    pub(super) encoding: &'static Encoding,
+    // some bytes were invalid and replaced with U+FFFD
+    pub(super) lossy: bool,
+    // the page declared a charset we don't know
+    pub(super) unknown: Option<String>,
+}
+
+// find the value of `charset=` in a Content-Type header or a <meta> tag
+fn charset_param(text: &str) -> Option<&str> {
+    let lower = text.to_ascii_lowercase();
+    let begin = lower.find("charset=")? + "charset=".len();
+
+    let value = text[begin..]
+        .trim_start_matches(|c| c == '"' || c == '\'');
+    let end = value
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '>' || c.is_whitespace())
+        .unwrap_or(value.len());
+
+    Some(&value[..end]).filter(|value| !value.is_empty())
+}
+
+// look for <meta charset="..."> (or its http-equiv form) in the head
+fn meta_charset(bytes: &[u8]) -> Option<&str> {
+    // the spec says it must be within the first 1024 bytes
+    let head = &bytes[..bytes.len().min(1024)];
+    let head = match std::str::from_utf8(head) {
+        Ok(head) => head,
+        // cut in the middle of a character, or not ascii-compatible at all
+        Err(err) => std::str::from_utf8(&head[..err.valid_up_to()]).ok()?,
+    };
+
+    head.match_indices("<meta")
+        .chain(head.match_indices("<META"))
+        .find_map(|(pos, _)| {
+            let tag = &head[pos..];
+            let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+            charset_param(tag)
+        })
+}
+
+// decode an html document as its charset says, falling back to UTF-8;
+// never fails, but tells whether anything had to be replaced
+pub(super) fn decode_html(bytes: &[u8], content_type: Option<&str>) -> Decoded {
+    let label = content_type
+        .and_then(charset_param)
+        .or_else(|| meta_charset(bytes));
+
+    let (encoding, unknown) = match label {
+        Some(label) => match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => (encoding, None),
+            None => (UTF_8, Some(label.to_owned())),
+        },
+        None => (UTF_8, None),
+    };
+
+    let (text, encoding, lossy) = encoding.decode(bytes);
+
+    Decoded {
+        text: text.into_owned(),
+        encoding,
+        lossy,
+        unknown,
+    }
+}
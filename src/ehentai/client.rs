@@ -2,75 +2,1167 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use std::str;
-use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
 
-use hyper::{Uri, Body, Request, Response};
+use bytes::Bytes;
+use hyper::{Uri, Body, Method, Request, Response, StatusCode};
+use hyper::http::request;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, COOKIE, REFERER};
+use hyper::header::{LOCATION, RETRY_AFTER, SET_COOKIE, USER_AGENT};
 use hyper::client::connect::HttpConnector;
+use hyper_rustls::HttpsConnectorBuilder;
 use detour::HttpsConnector;
 use select::document::Document;
+use tokio::time::timeout;
 
-type ErrorBox = Box<dyn Error>;
-type Connector = HttpsConnector<HttpConnector>;
+use crate::Error;
+use super::parser::{self, ParseError};
+use super::retry::RetryPolicy;
+use super::limit::{Limiter, RateLimit};
+use super::charset;
+use super::stream::ImageStream;
+use super::download;
+use super::cookie::{self, Cookie, CookieJar};
+use super::proxy::{Proxy, ProxyConnector};
+use super::identity::{self, Identity, IdentityStats, Usage};
+use super::transport::{BoxFuture, Transport};
+use super::record::{self, RecordTransport, ReplayTransport};
+use super::cache::{Cache, CachePolicy, PageKind};
+use super::coalesce::{Inflight, Joined};
+
+const DEFAULT_USER_AGENT: &str = concat!("lude/", env!("CARGO_PKG_VERSION"));
+const HTML: &str = "text/html,application/xhtml+xml";
+const EHENTAI: &str = "https://e-hentai.org/";
+const EXHENTAI: &str = "https://exhentai.org/";
+
+type DetourConnector = HttpsConnector<ProxyConnector>;
+type PlainConnector = hyper_rustls::HttpsConnector<ProxyConnector>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http1,
+    // negotiated with ALPN, so it needs detour to be disabled
+    Http2,
+}
+
+// something went wrong but we could carry on anyway
+#[derive(Debug, Clone)]
+pub enum Warning {
+    // the page had bytes invalid in its charset, which were replaced with U+FFFD
+    LossyDecoding { url: String, encoding: &'static str },
+    // the page declared a charset we don't know, so it was read as UTF-8
+    UnknownCharset { url: String, label: String },
+}
+
+pub(super) type WarningHook = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+// hyper::Client is generic over its connector, so keep either of them
+enum Inner {
+    Detour(hyper::Client<DetourConnector, Body>),
+    Plain(hyper::Client<PlainConnector, Body>),
+}
+
+impl Transport for Inner {
+    fn send(&self, req: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        let res = match self {
+            Inner::Detour(client) => client.request(req),
+            Inner::Plain(client) => client.request(req),
+        };
+
+        Box::pin(async move { Ok(res.await?) })
+    }
+}
+
+pub struct ClientBuilder {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    version: HttpVersion,
+    detour: bool,
+    retry: RetryPolicy,
+    page_limit: RateLimit,
+    image_limit: RateLimit,
+    max_concurrent: usize,
+    cookies: CookieJar,
+    exhentai: bool,
+    base_url: Option<String>,
+    page_proxy: Option<Proxy>,
+    image_proxy: Option<Proxy>,
+    identities: Vec<Identity>,
+    retire_for: Duration,
+    transport: Option<Arc<dyn Transport>>,
+    record: Option<PathBuf>,
+    cache: Option<CachePolicy>,
+    failover: u32,
+    on_warning: Option<WarningHook>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            connect_timeout: None,
+            // image servers are run by volunteers, and some of them stall;
+            // without a limit we'd never get to fail over to another one
+            read_timeout: Some(Duration::from_secs(30)),
+            total_timeout: None,
+            user_agent: Some(String::from(DEFAULT_USER_AGENT)),
+            headers: Vec::new(),
+            version: HttpVersion::Http1,
+            detour: true,
+            retry: RetryPolicy::new(),
+            page_limit: RateLimit::new(3.0, 5),
+            image_limit: RateLimit::new(10.0, 10),
+            max_concurrent: 8,
+            cookies: CookieJar::new(),
+            exhentai: false,
+            base_url: None,
+            page_proxy: None,
+            image_proxy: None,
+            identities: Vec::new(),
+            retire_for: Duration::from_secs(60 * 60),
+            transport: None,
+            record: None,
+            cache: None,
+            failover: 2,
+            on_warning: None,
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.total_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, agent: &str) -> Self {
+        self.user_agent = Some(agent.to_owned());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn http_version(mut self, version: HttpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn detour(mut self, enabled: bool) -> Self {
+        self.detour = enabled;
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn rate_limits(mut self, pages: RateLimit, images: RateLimit) -> Self {
+        self.page_limit = pages;
+        self.image_limit = images;
+        self
+    }
+
+    pub fn max_concurrent(mut self, n: usize) -> Self {
+        self.max_concurrent = n;
+        self
+    }
+
+    pub fn cookies(mut self, member_id: &str, pass_hash: &str) -> Self {
+        for cookie in cookie::member(member_id, pass_hash) {
+            self.cookies.insert(cookie);
+        }
+
+        self
+    }
+
+    // start with the cookies of a previous session
+    pub fn cookie_jar(mut self, jar: CookieJar) -> Self {
+        for cookie in jar.iter() {
+            self.cookies.insert(cookie.clone());
+        }
+
+        self
+    }
+
+    // the cookie exhentai gives to accounts allowed in
+    pub fn igneous(mut self, value: &str) -> Self {
+        self.cookies.insert(Cookie::new("exhentai.org", "igneous", value));
+        self
+    }
+
+    pub fn exhentai(mut self, enabled: bool) -> Self {
+        self.exhentai = enabled;
+        self
+    }
+
+    pub fn base_url(mut self, url: &str) -> Self {
+        self.base_url = Some(url.to_owned());
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.page_proxy = Some(proxy.clone());
+        self.image_proxy = Some(proxy);
+        self
+    }
+
+    pub fn page_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.page_proxy = proxy;
+        self
+    }
+
+    pub fn image_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.image_proxy = proxy;
+        self
+    }
+
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identities.push(identity);
+        self
+    }
+
+    pub fn retire_for(mut self, duration: Duration) -> Self {
+        self.retire_for = duration;
+        self
+    }
+
+    // replaces the whole networking stack, so the settings
+    // for connections (proxies, detour, ...) don't matter anymore
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn record(mut self, dir: impl AsRef<Path>) -> Self {
+        self.record = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn replay(self, dir: impl AsRef<Path>) -> Self {
+        self.transport(Arc::new(ReplayTransport::new(dir)))
+    }
+
+    pub fn cache(mut self, policy: CachePolicy) -> Self {
+        self.cache = Some(policy);
+        self
+    }
+
+    pub fn failover(mut self, hops: u32) -> Self {
+        self.failover = hops;
+        self
+    }
+
+    pub fn on_warning(mut self, hook: WarningHook) -> Self {
+        self.on_warning = Some(hook);
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let base = match &self.base_url {
+            Some(url) => url.as_str(),
+            None if self.exhentai => EXHENTAI,
+            None => EHENTAI,
+        };
+
+        let base = base.parse::<Uri>()?;
+
+        // detour doesn't tell what it negotiated, and sending HTTP/2
+        // to a server that doesn't speak it fails every request
+        if self.detour && self.version == HttpVersion::Http2 && self.transport.is_none() {
+            return Err(Error::InvalidInput("HTTP/2 through detour".to_owned()));
+        }
+
+        if base.scheme().is_none() || base.host().is_none() {
+            return Err(Error::InvalidInput(format!("base url {:?}", base.to_string())));
+        }
+
+        // a base url given explicitly (e.g. a local server) stands in for both
+        let home = match &self.base_url {
+            Some(_) => base.clone(),
+            None => EHENTAI.parse::<Uri>()?,
+        };
+
+        let mut headers = HeaderMap::new();
+
+        if let Some(agent) = &self.user_agent {
+            headers.insert(USER_AGENT, header_value(agent)?);
+        }
+
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::InvalidInput(format!("header name {:?}", name)))?;
+
+            headers.append(name, header_value(value)?);
+        }
+
+        // without a pool, there's just one identity which is never retired
+        let rotating = !self.identities.is_empty();
+        let identities = if rotating {
+            self.identities.clone()
+        } else {
+            vec![Identity::new("default")]
+        };
+
+        let slots = identities
+            .into_iter()
+            .map(|identity| {
+                let transport = match (&self.transport, &identity.proxy) {
+                    (Some(transport), _) => transport.clone(),
+                    (None, Some(proxy)) => self.connect(Some(proxy.clone()), Some(proxy.clone())),
+                    (None, None) => self.connect(self.page_proxy.clone(), self.image_proxy.clone()),
+                };
+
+                let transport = match &self.record {
+                    Some(dir) => Arc::new(RecordTransport::new(transport, dir)),
+                    None => transport,
+                };
+
+                let mut cookies = self.cookies.clone();
+
+                for cookie in identity.cookies.iter() {
+                    cookies.insert(cookie.clone());
+                }
+
+                Slot {
+                    name: identity.name,
+                    transport,
+                    cookies: Mutex::new(cookies),
+                    usage: Usage::default(),
+                }
+            })
+            .collect();
+
+        Ok(Client {
+            slots,
+            next: AtomicUsize::new(0),
+            rotating,
+            retire_for: self.retire_for,
+            base,
+            home,
+            exhentai: self.exhentai,
+            headers,
+            read_timeout: self.read_timeout,
+            total_timeout: self.total_timeout,
+            retry: self.retry,
+            limiter: Limiter::new(self.page_limit, self.image_limit, self.max_concurrent),
+            cache: self.cache.map(Cache::open),
+            pages: Inflight::new(),
+            images: Inflight::new(),
+            failover: self.failover,
+            on_warning: self.on_warning,
+        })
+    }
+
+    fn connect(&self, pages: Option<Proxy>, images: Option<Proxy>) -> Arc<dyn Transport> {
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(self.connect_timeout);
+        // the tls connectors take care of https
+        http.enforce_http(false);
+
+        let connector = ProxyConnector::new(http, pages, images);
+
+        let builder = hyper::Client::builder();
+
+        // build() makes sure this is HTTP/1
+        let inner = if self.detour {
+            Inner::Detour(builder.build(HttpsConnector::new_with_connector(connector)))
+        } else {
+            let tls = HttpsConnectorBuilder::new()
+                .with_webpki_roots()
+                .https_or_http()
+                .enable_http1();
+
+            let tls = match self.version {
+                HttpVersion::Http1 => tls.wrap_connector(connector),
+                HttpVersion::Http2 => tls.enable_http2().wrap_connector(connector),
+            };
+
+            Inner::Plain(builder.build(tls))
+        };
+
+        Arc::new(inner)
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value)
+        .map_err(|_| Error::InvalidInput(format!("header value {:?}", value)))
+}
+
+// give up waiting for `f` after `limit` if there's one
+async fn timed<F: Future>(limit: Option<Duration>, f: F) -> Result<F::Output, Error> {
+    match limit {
+        Some(limit) => timeout(limit, f).await.map_err(|_| Error::Timeout),
+        None => Ok(f.await),
+    }
+}
+
+// an identity along with its own connections and cookies
+struct Slot {
+    name: String,
+    transport: Arc<dyn Transport>,
+    cookies: Mutex<CookieJar>,
+    usage: Usage,
+}
 
 pub struct Client {
-    inner: hyper::Client<Connector, Body>,
-    cookie: Option<String>,
+    slots: Vec<Slot>,
+    // where round-robin picks the next identity from
+    next: AtomicUsize,
+    // whether identities are retired when they run into trouble
+    rotating: bool,
+    retire_for: Duration,
+    // where searches go, e.g. "https://e-hentai.org/"
+    base: Uri,
+    // where the account pages are, which exhentai doesn't have
+    home: Uri,
+    exhentai: bool,
+    headers: HeaderMap,
+    read_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    retry: RetryPolicy,
+    limiter: Limiter,
+    cache: Option<Cache>,
+    // requests being made now, for the same ones to wait for
+    // pages are told apart by whether they may come from the cache too
+    pages: Inflight<(String, bool), Fetched>,
+    images: Inflight<String, Vec<u8>>,
+    // how many other image servers to try when one fails
+    failover: u32,
+    on_warning: Option<WarningHook>,
+}
+
+// what the link to an original image answers with
+pub(super) enum FullImage {
+    // where the image actually is; the usual answer
+    Redirect(Uri),
+    Image(Vec<u8>),
+    // a page telling why we can't have it
+    Notice(String),
+}
+
+// a page as it came, for those who wait for it to decode on their own
+// (a Document can't be sent to other threads)
+#[derive(Clone)]
+struct Fetched {
+    body: Bytes,
+    content_type: Option<String>,
 }
 
 impl Client {
     pub fn new() -> Self {
-        let https = HttpsConnector::new();
-        let inner = hyper::Client::builder()
-            .build::<_, Body>(https);
+        // the default configuration is always valid
+        ClientBuilder::new().build().unwrap()
+    }
 
-        Self {
-            inner,
-            cookie: None,
+    fn warn(&self, warning: Warning) {
+        if let Some(hook) = &self.on_warning {
+            hook(&warning);
+        }
+    }
+
+    // the policy used unless a call gives its own
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn set_cookies(&self, member_id: &str, pass_hash: &str) {
+        for slot in &self.slots {
+            let mut jar = slot.cookies.lock().unwrap();
+
+            for cookie in cookie::member(member_id, pass_hash) {
+                jar.insert(cookie);
+            }
         }
     }
 
-    pub fn set_cookies(&mut self, member_id: &str, pass_hash: &str) {
-        self.cookie = Some(format!(
-            "ipb_member_id={}; ipb_pass_hash={}",
-            member_id, pass_hash
-        ));
+    pub(super) fn base(&self) -> &Uri {
+        &self.base
     }
 
-    async fn get(&self, dest: Uri, mime: &str)
-        -> Result<Response<Body>, ErrorBox> {
-        let req = if dest.host() != Some("e-hentai.org") || self.cookie.is_none() {
-            Request::get(dest)
-                .header("Content-Type", mime)
-                .body(Body::empty())?
-        } else if let Some(cookie) = self.cookie.as_ref() {
-            Request::get(dest)
-                .header("Content-Type", mime)
-                .header("Cookie", cookie.as_str())
-                .body(Body::empty())?
+    pub(super) fn home(&self) -> &Uri {
+        &self.home
+    }
+
+    pub(super) fn failover(&self) -> u32 {
+        self.failover
+    }
+
+    // whether we have an account to make requests with;
+    // guests get "0" as their member id
+    pub(super) fn logged_in(&self) -> bool {
+        self.slots[0].cookies
+            .lock()
+            .unwrap()
+            .get(self.base.host().unwrap_or_default(), "ipb_member_id")
+            .map_or(false, |cookie| cookie.value() != "0")
+    }
+
+    // a snapshot of the cookies we have now, e.g. to save them;
+    // those of the first identity if there are many
+    pub fn cookies(&self) -> CookieJar {
+        self.slots[0].cookies.lock().unwrap().clone()
+    }
+
+    pub fn identities(&self) -> Vec<IdentityStats> {
+        self.slots
+            .iter()
+            .map(|slot| slot.usage.stats(&slot.name))
+            .collect()
+    }
+
+    // the next identity which isn't retired
+    fn pick(&self) -> Result<&Slot, Error> {
+        if !self.rotating {
+            return Ok(&self.slots[0]);
+        }
+
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.slots.len();
+
+        let slot = (0..len)
+            .map(|i| &self.slots[(start + i) % len])
+            .find(|slot| slot.usage.retired_for(now).is_none());
+
+        // all of them are resting; tell when the first one is back
+        slot.ok_or_else(|| Error::RateLimited(
+            self.slots.iter().filter_map(|slot| slot.usage.retired_for(now)).min()
+        ))
+    }
+
+    // run `f` as an identity, moving on to the next one if it turns out
+    // to be banned, out of quota or logged out
+    async fn rotate<'a, T, F, Fut>(&'a self, f: F) -> Result<T, Error>
+    where
+        F: Fn(&'a Slot) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        loop {
+            let slot = self.pick()?;
+
+            let err = match f(slot).await {
+                Err(err) if self.rotating => err,
+                res => return res,
+            };
+
+            match identity::retirement(&err, self.retire_for) {
+                Some(duration) => slot.usage.retire(duration),
+                None => return Err(err),
+            }
+
+            // nobody left to take over
+            let now = Instant::now();
+
+            if self.slots.iter().all(|slot| slot.usage.retired_for(now).is_some()) {
+                return Err(err);
+            }
+        }
+    }
+
+    async fn get(&self, slot: &Slot, dest: Uri, accept: &str, referer: Option<&str>)
+        -> Result<Response<Body>, Error> {
+        self.get_with_headers(slot, dest, accept, referer, HeaderMap::new()).await
+    }
+
+    // same as get(), with some more headers (e.g. Range) for this request only
+    async fn get_with_headers(
+        &self,
+        slot: &Slot,
+        dest: Uri,
+        accept: &str,
+        referer: Option<&str>,
+        extra: HeaderMap
+    ) -> Result<Response<Body>, Error> {
+        let req = self.request(slot, Method::GET, &dest, accept, referer, extra)?;
+        let res = self.send(slot, &dest, req.body(Body::empty())?).await?;
+
+        self.check(res).await
+    }
+
+    // post a form (e.g. the login form), returning the page we're sent to;
+    // always as the first identity, whose cookies cookies() and logged_in()
+    // look at, so that what the form sets ends up where it's looked for
+    pub(super) async fn post_form(&self, dest: Uri, fields: &[(&str, &str)])
+        -> Result<Document, Error> {
+        self.retry.run(|| self.submit_form(dest.clone(), fields)).await
+    }
+
+    async fn submit_form(&self, dest: Uri, fields: &[(&str, &str)])
+        -> Result<Document, Error> {
+        let _permit = self.limiter.acquire(dest.host().unwrap_or_default()).await;
+
+        let url = dest.to_string();
+        let slot = &self.slots[0];
+
+        let req = self.request(slot, Method::POST, &dest, HTML, None, HeaderMap::new())?
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form_encode(fields)))?;
+
+        let (bytes, content_type) = timed(self.total_timeout, async {
+            let res = self.send(slot, &dest, req).await?;
+
+            // being redirected is how a form usually says it's done
+            let res = if res.status().is_redirection() {
+                res
+            } else {
+                self.check(res).await?
+            };
+
+            let content_type = content_type(&res);
+            Ok::<_, Error>((self.read_body(res).await?, content_type))
+        }).await??;
+
+        self.html(url, &bytes, content_type.as_deref())
+    }
+
+    // a request with our usual headers and cookies
+    fn request(
+        &self,
+        slot: &Slot,
+        method: Method,
+        dest: &Uri,
+        accept: &str,
+        referer: Option<&str>,
+        extra: HeaderMap
+    ) -> Result<request::Builder, Error> {
+        // images are compressed already; don't bother the server with it
+        let encoding = if accept.starts_with("image/") {
+            "identity"
         } else {
-            // logically impossible
-            unreachable!()
+            "gzip, deflate, br"
         };
 
-        let res = self.inner.request(req).await?;
+        let mut req = Request::builder()
+            .method(method)
+            .uri(dest.clone())
+            .header(ACCEPT, accept)
+            .header(ACCEPT_ENCODING, encoding);
+
+        for (name, value) in self.headers.iter() {
+            req = req.header(name, value);
+        }
+
+        if let Some(referer) = referer {
+            req = req.header(REFERER, referer);
+        }
+
+        for (name, value) in extra.iter() {
+            req = req.header(name, value);
+        }
+
+        if let Some(cookie) = slot.cookies.lock().unwrap().header(dest) {
+            req = req.header(COOKIE, cookie);
+        }
+
+        Ok(req)
+    }
+
+    // send the request to `dest`, remembering the cookies it sets
+    async fn send(&self, slot: &Slot, dest: &Uri, req: Request<Body>)
+        -> Result<Response<Body>, Error> {
+        slot.usage.used();
+        let res = timed(self.read_timeout, slot.transport.send(req)).await??;
+
+        // error pages may set cookies as well
+        {
+            let mut jar = slot.cookies.lock().unwrap();
+
+            for value in res.headers().get_all(SET_COOKIE) {
+                if let Ok(value) = value.to_str() {
+                    jar.store(dest, value);
+                }
+            }
+        }
+
         Ok(res)
     }
 
-    pub async fn get_image(&self, dest: Uri)
-        -> Result<Vec<u8>, ErrorBox> {
-        let res = self.get(dest, "image/*").await?;
-        let bytes = hyper::body::to_bytes(res.into_body()).await?;
-    
-        Ok(bytes.to_vec())
+    // turn an unsuccessful response into an error
+    async fn check(&self, res: Response<Body>) -> Result<Response<Body>, Error> {
+        if res.status().is_success() {
+            return Ok(res);
+        }
+
+        // error pages may still tell us something useful (e.g. a ban notice)
+        let status = res.status();
+        let retry_after = res.headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+
+        let bytes = self.read_body(res).await?;
+        check_notice(&String::from_utf8_lossy(&bytes))?;
+
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Err(Error::NotFound),
+            StatusCode::TOO_MANY_REQUESTS => Err(Error::RateLimited(retry_after)),
+            StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => {
+                Err(Error::RateLimited(retry_after))
+            },
+            // "509 Bandwidth Limit Exceeded" isn't a standard one
+            status if status.as_u16() == 509 => Err(Error::QuotaExceeded),
+            status => Err(Error::Status(status)),
+        }
+    }
+
+    // read the whole body, giving up if the server stalls for too long
+    async fn read_body(&self, res: Response<Body>) -> Result<Vec<u8>, Error> {
+        let encoding = res.headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+
+        let mut body = res.into_body();
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = timed(self.read_timeout, body.data()).await? {
+            buf.extend_from_slice(&chunk?);
+        }
+
+        decode(encoding.as_deref(), buf)
+    }
+
+    // `referer` should be the page the image is shown in;
+    // some image servers refuse requests without it
+    pub async fn get_image(&self, dest: Uri, referer: &str)
+        -> Result<Vec<u8>, Error> {
+        self.get_image_with(dest, referer, &self.retry).await
+    }
+
+    pub async fn get_image_with(&self, dest: Uri, referer: &str, retry: &RetryPolicy)
+        -> Result<Vec<u8>, Error> {
+        let key = record::normalize(&dest);
+
+        let image = self.images.run(
+            &key,
+            || retry.run(|| self.fetch_image(dest.clone(), referer)),
+            Vec::clone
+        ).await?;
+
+        Ok(image.into_inner())
+    }
+
+    // open the image without reading it; the retry policy only
+    // applies until the response arrives
+    pub async fn stream_image(&self, dest: Uri, referer: &str, retry: &RetryPolicy)
+        -> Result<ImageStream, Error> {
+        retry.run(|| self.open_image(dest.clone(), referer, HeaderMap::new())).await
+    }
+
+    // download the image into a file, resuming from where
+    // a previous (interrupted) download stopped if possible;
+    // `hash` is the beginning of SHA-1 of the image, if known
+    pub async fn download(
+        &self,
+        dest: Uri,
+        referer: &str,
+        path: &Path,
+        hash: Option<&str>,
+        retry: &RetryPolicy
+    ) -> Result<u64, Error> {
+        retry.run(|| download::resume(self, dest.clone(), referer, path, hash)).await
     }
 
     pub async fn get_html(&self, dest: Uri)
-        -> Result<Document, ErrorBox> {
-        let res = self.get(dest, "text/html").await?;
-        let bytes = hyper::body::to_bytes(res.into_body()).await?;
-        let file = str::from_utf8(&bytes)?;
-    
-        Ok(Document::from(file))
+        -> Result<Document, Error> {
+        self.get_html_with(dest, &self.retry).await
+    }
+
+    pub async fn get_html_with(&self, dest: Uri, retry: &RetryPolicy)
+        -> Result<Document, Error> {
+        self.load_html(dest, retry, false).await
+    }
+
+    // with `bypass_cache`, the page is fetched even if there's a fresh
+    // copy in the cache, which is then replaced with what we get
+    pub async fn load_html(&self, dest: Uri, retry: &RetryPolicy, bypass_cache: bool)
+        -> Result<Document, Error> {
+        // a bypassing call mustn't be handed what came from the cache
+        let key = (record::normalize(&dest), bypass_cache);
+
+        let page = self.pages.run(
+            &key,
+            || retry.run(|| self.fetch_html(dest.clone(), bypass_cache)),
+            |(_, fetched)| fetched.clone()
+        ).await?;
+
+        match page {
+            Joined::Led((doc, _)) => Ok(doc),
+            Joined::Followed(page) => {
+                self.page(dest.to_string(), &page.body, page.content_type.as_deref())
+            },
+        }
+    }
+
+    async fn fetch_image(&self, dest: Uri, referer: &str)
+        -> Result<Vec<u8>, Error> {
+        // image pages point to this placeholder instead of the actual image
+        // once the image quota is exhausted; no need to download it
+        if dest.path().ends_with("/509.gif") {
+            return Err(Error::QuotaExceeded);
+        }
+
+        let _permit = self.limiter.acquire(dest.host().unwrap_or_default()).await;
+
+        self.rotate(|slot| {
+            let dest = dest.clone();
+
+            timed(self.total_timeout, async move {
+                let res = self.get(slot, dest, "image/*", Some(referer)).await?;
+                self.read_body(res).await
+            })
+        }).await?
+    }
+
+    // ask the link to an original image where the image is,
+    // without following it; see FullImage
+    pub(super) async fn full_image(&self, dest: Uri, referer: &str, retry: &RetryPolicy)
+        -> Result<FullImage, Error> {
+        retry.run(|| self.fetch_full_image(dest.clone(), referer)).await
+    }
+
+    async fn fetch_full_image(&self, dest: Uri, referer: &str)
+        -> Result<FullImage, Error> {
+        let _permit = self.limiter.acquire(dest.host().unwrap_or_default()).await;
+
+        self.rotate(|slot| {
+            let dest = dest.clone();
+
+            timed(self.total_timeout, async move {
+                let accept = "image/*,text/html;q=0.9";
+                let req = self
+                    .request(slot, Method::GET, &dest, accept, Some(referer), HeaderMap::new())?
+                    .body(Body::empty())?;
+                let res = self.send(slot, &dest, req).await?;
+
+                if res.status().is_redirection() {
+                    let location = res.headers()
+                        .get(LOCATION)
+                        .and_then(|value| value.to_str().ok())
+                        .ok_or_else(|| ParseError::new("fullimg: redirect without Location"))?;
+
+                    return Ok(FullImage::Redirect(resolve(&dest, location)?));
+                }
+
+                let res = self.check(res).await?;
+                let image = content_type(&res).map_or(false, |value| value.starts_with("image/"));
+                let bytes = self.read_body(res).await?;
+
+                if image {
+                    Ok(FullImage::Image(bytes))
+                } else {
+                    let text = String::from_utf8_lossy(&bytes).into_owned();
+                    check_notice(&text)?;
+
+                    Ok(FullImage::Notice(text))
+                }
+            })
+        }).await?
+    }
+
+    pub(super) async fn open_image(&self, dest: Uri, referer: &str, extra: HeaderMap)
+        -> Result<ImageStream, Error> {
+        if dest.path().ends_with("/509.gif") {
+            return Err(Error::QuotaExceeded);
+        }
+
+        let permit = self.limiter.acquire(dest.host().unwrap_or_default()).await;
+        let res = self.rotate(|slot| timed(
+            self.total_timeout,
+            self.get_with_headers(slot, dest.clone(), "image/*", Some(referer), extra.clone())
+        )).await??;
+
+        Ok(ImageStream::new(res, Some(permit), self.read_timeout))
+    }
+
+    async fn fetch_html(&self, dest: Uri, bypass_cache: bool)
+        -> Result<(Document, Fetched), Error> {
+        self.rotate(|slot| self.fetch_html_as(slot, dest.clone(), bypass_cache)).await
+    }
+
+    async fn fetch_html_as(&self, slot: &Slot, dest: Uri, bypass_cache: bool)
+        -> Result<(Document, Fetched), Error> {
+        let url = dest.to_string();
+        let members_only = self.exhentai && dest.authority() == self.base.authority();
+
+        // what the page would be kept under, if it's kept at all
+        let cached = match (&self.cache, PageKind::of(&dest)) {
+            (Some(cache), Some(kind)) => {
+                let cookies = slot.cookies.lock().unwrap().header(&dest);
+                Some((cache, kind, Cache::key(&dest, cookies.as_deref())))
+            },
+            _ => None,
+        };
+
+        if let Some((cache, kind, key)) = cached.as_ref().filter(|_| !bypass_cache) {
+            if let Some(page) = cache.get(key, &dest, *kind).await {
+                let doc = self.page(url, &page.body, page.content_type.as_deref())?;
+
+                return Ok((doc, Fetched {
+                    body: page.body.into(),
+                    content_type: page.content_type,
+                }));
+            }
+        }
+
+        let _permit = self.limiter.acquire(dest.host().unwrap_or_default()).await;
+
+        let (bytes, content_type) = timed(self.total_timeout, async {
+            let res = self.get(slot, dest.clone(), HTML, None).await?;
+            let content_type = content_type(&res);
+
+            Ok::<_, Error>((self.read_body(res).await?, content_type))
+        }).await??;
+
+        // exhentai answers those who aren't allowed in with a blank page
+        // (it used to be a picture of a sad panda) instead of an error
+        let blank = bytes.iter().all(u8::is_ascii_whitespace)
+            || content_type.as_deref().map_or(false, |value| value.starts_with("image/"));
+
+        if members_only && blank {
+            return Err(Error::Unauthorized);
+        }
+
+        let text = self.decode(url, &bytes, content_type.as_deref())?;
+
+        // a document can't be held across an await, so the page is kept
+        // before it's parsed, and taken out again if it isn't fine
+        if let Some((cache, kind, key)) = &cached {
+            cache.put(key, &dest, *kind, &bytes, content_type.as_deref()).await;
+        }
+
+        let err = match self.document(&text) {
+            Ok(doc) => {
+                return Ok((doc, Fetched {
+                    body: bytes.into(),
+                    content_type,
+                }));
+            },
+            Err(err) => err,
+        };
+
+        if let Some((cache, _, key)) = &cached {
+            cache.forget(key).await;
+        }
+
+        Err(err)
+    }
+
+    fn page(&self, url: String, bytes: &[u8], content_type: Option<&str>)
+        -> Result<Document, Error> {
+        let text = self.decode(url, bytes, content_type)?;
+        self.document(&text)
+    }
+
+    // an image page showing the placeholder means this identity
+    // has run out of its quota
+    fn document(&self, text: &str) -> Result<Document, Error> {
+        let doc = html(text)?;
+
+        if parser::image(&doc).map_or(false, |src| src.ends_with("/509.gif")) {
+            return Err(Error::QuotaExceeded);
+        }
+
+        Ok(doc)
+    }
+
+    fn html(&self, url: String, bytes: &[u8], content_type: Option<&str>)
+        -> Result<Document, Error> {
+        html(&self.decode(url, bytes, content_type)?)
+    }
+
+    fn decode(&self, url: String, bytes: &[u8], content_type: Option<&str>)
+        -> Result<String, Error> {
+        // an encoding glitch in a comment shouldn't cost us the whole page
+        let file = charset::decode_html(bytes, content_type);
+
+        if let Some(label) = file.unknown {
+            self.warn(Warning::UnknownCharset { url: url.clone(), label });
+        }
+
+        if file.lossy {
+            self.warn(Warning::LossyDecoding { url, encoding: file.encoding.name() });
+        }
+
+        check_notice(&file.text)?;
+        Ok(file.text)
+    }
+}
+
+fn html(text: &str) -> Result<Document, Error> {
+    let doc = Document::from(text);
+
+    if let Some(reason) = parser::unavailable(&doc) {
+        return Err(Error::Removed(reason));
+    }
+
+    Ok(doc)
+}
+
+// a Location header may be relative to where it came from
+fn resolve(base: &Uri, location: &str) -> Result<Uri, Error> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        let scheme = base.scheme_str().unwrap_or("https");
+        let authority = base.authority().map_or("", |auth| auth.as_str());
+
+        Ok(format!("{}://{}{}", scheme, authority, location).parse()?)
+    } else if let Some(rest) = location.strip_prefix("//") {
+        Ok(format!("{}://{}", base.scheme_str().unwrap_or("https"), rest).parse()?)
+    } else {
+        Ok(location.parse()?)
+    }
+}
+
+fn content_type(res: &Response<Body>) -> Option<String> {
+    res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+// encode a form as application/x-www-form-urlencoded
+fn form_encode(fields: &[(&str, &str)]) -> String {
+    let encode = |text: &str| {
+        let mut out = String::new();
+
+        for byte in text.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                    out.push(byte as char);
+                },
+                b' ' => out.push('+'),
+                byte => out.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+
+        out
+    };
+
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// undo Content-Encoding of a response body
+pub(super) fn decode(encoding: Option<&str>, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+
+    let res = match encoding {
+        None | Some("") | Some("identity") => return Ok(bytes),
+        Some("gzip") | Some("x-gzip") => {
+            flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut buf)
+        },
+        Some("deflate") => {
+            // it's supposed to be zlib-wrapped, but some servers send raw deflate
+            flate2::read::ZlibDecoder::new(&bytes[..])
+                .read_to_end(&mut buf)
+                .or_else(|_| {
+                    buf.clear();
+                    flate2::read::DeflateDecoder::new(&bytes[..]).read_to_end(&mut buf)
+                })
+        },
+        Some("br") => {
+            brotli_decompressor::Decompressor::new(&bytes[..], 4096).read_to_end(&mut buf)
+        },
+        Some(other) => {
+            return Err(ParseError::new(
+                format!("unsupported content encoding {:?}", other)
+            ).into());
+        },
+    };
+
+    match res {
+        Ok(_) => Ok(buf),
+        Err(err) => Err(ParseError::new(
+            format!("malformed {} response body ({})", encoding.unwrap_or_default(), err)
+        ).into()),
+    }
+}
+
+// the site answers some requests with a plain text notice instead of
+// the page we asked for; turn those into errors
+fn check_notice(text: &str) -> Result<(), Error> {
+    let text = text.trim_start();
+
+    if text.starts_with("Your IP address has been temporarily banned") {
+        Err(Error::Banned(ban_expiry(text)))
+    } else if text.starts_with("Key missing, or incorrect key provided") {
+        Err(Error::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+// Retry-After is either a number of seconds or a date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+// read how long a ban lasts from the notice, which looks like
+// "... The ban expires in 2 days, 23 hours and 59 minutes"
+pub(super) fn ban_expiry(text: &str) -> Option<Duration> {
+    let (_, rest) = text.split_once("The ban expires in ")?;
+    let mut secs = 0;
+    let mut found = false;
+    let mut words = rest.split(|c: char| c.is_whitespace() || c == ',');
+
+    while let Some(word) = words.next() {
+        let n = match word.parse::<u64>() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+
+        let unit = match words.next()?.trim_end_matches('.') {
+            "day" | "days" => 24 * 60 * 60,
+            "hour" | "hours" => 60 * 60,
+            "minute" | "minutes" => 60,
+            "second" | "seconds" => 1,
+            _ => break,
+        };
+
+        // a notice we can't make sense of tells no expiry
+        secs = n.checked_mul(unit).and_then(|n| n.checked_add(secs))?;
+        found = true;
+    }
+
+    if found {
+        Some(Duration::from_secs(secs))
+    } else {
+        None
     }
 }
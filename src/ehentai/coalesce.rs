@@ -0,0 +1,140 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+use crate::Error;
+
+// how a call to Inflight::run() got its result
+pub(super) enum Joined<R, T> {
+    // we made the request ourselves
+    Led(R),
+    // someone else was making the same request; this is their result
+    Followed(T),
+}
+
+impl<T> Joined<T, T> {
+    pub(super) fn into_inner(self) -> T {
+        match self {
+            Joined::Led(value) | Joined::Followed(value) => value,
+        }
+    }
+}
+
+type Waiters<T> = Vec<oneshot::Sender<Result<T, Arc<Error>>>>;
+
+// requests being made right now, along with those waiting for them;
+// the same request made meanwhile waits for the first one to finish
+// instead of going to the server again; it gets the first one's result
+// as it is, retries and all, so its own retry policy never comes into play
+pub(super) struct Inflight<K, T> {
+    waiting: Mutex<HashMap<K, Waiters<T>>>,
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Inflight<K, T> {
+    pub(super) fn new() -> Self {
+        Self {
+            waiting: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // run `f` unless a request under the same `key` is running already;
+    // `share` makes what's handed to the others out of our result
+    pub(super) async fn run<R, F, Fut, S>(&self, key: &K, f: F, share: S)
+        -> Result<Joined<R, T>, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<R, Error>>,
+        S: FnOnce(&R) -> T,
+    {
+        loop {
+            let rx = {
+                let mut waiting = self.waiting.lock().unwrap();
+
+                match waiting.get_mut(key) {
+                    Some(waiters) => {
+                        let (tx, rx) = oneshot::channel();
+                        waiters.push(tx);
+                        rx
+                    },
+                    None => {
+                        waiting.insert(key.clone(), Vec::new());
+                        break;
+                    },
+                }
+            };
+
+            // the one we waited for was cancelled; see if we can lead this time
+            if let Ok(res) = rx.await {
+                return res.map(Joined::Followed).map_err(Error::from);
+            }
+        }
+
+        let mut lead = Lead {
+            inflight: self,
+            key,
+            done: false,
+        };
+
+        let res = f().await;
+        let waiters = lead.finish();
+
+        if waiters.is_empty() {
+            return res.map(Joined::Led);
+        }
+
+        match res {
+            Ok(value) => {
+                let shared = share(&value);
+
+                for tx in waiters {
+                    let _ = tx.send(Ok(shared.clone()));
+                }
+
+                Ok(Joined::Led(value))
+            },
+            Err(err) => {
+                let err = Arc::new(err);
+
+                for tx in waiters {
+                    let _ = tx.send(Err(err.clone()));
+                }
+
+                Err(err.into())
+            },
+        }
+    }
+}
+
+// lets the others know when the leading request is dropped halfway
+struct Lead<'a, K: Eq + Hash, T> {
+    inflight: &'a Inflight<K, T>,
+    key: &'a K,
+    done: bool,
+}
+
+impl<K: Eq + Hash, T> Lead<'_, K, T> {
+    fn finish(&mut self) -> Waiters<T> {
+        self.done = true;
+
+        self.inflight.waiting
+            .lock()
+            .unwrap()
+            .remove(self.key)
+            .unwrap_or_default()
+    }
+}
+
+impl<K: Eq + Hash, T> Drop for Lead<'_, K, T> {
+    fn drop(&mut self) {
+        // dropping the senders wakes up the waiters
+        if !self.done {
+            self.inflight.waiting.lock().unwrap().remove(self.key);
+        }
+    }
+}
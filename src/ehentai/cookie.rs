@@ -0,0 +1,301 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hyper::Uri;
+
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    // sent to the exact host only, not to its subdomains
+    host_only: bool,
+    path: String,
+    secure: bool,
+    // None if it only lasts for the session
+    expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    // a cookie for `domain` and all its subdomains, which never expires
+    pub fn new(domain: &str, name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            host_only: false,
+            path: String::from("/"),
+            secure: false,
+            expires: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    // whether it's sent to `host`
+    fn belongs(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        host == self.domain || (!self.host_only && within(&host, &self.domain))
+    }
+
+    fn matches(&self, uri: &Uri) -> bool {
+        let domain = self.belongs(uri.host().unwrap_or_default());
+        let path = uri.path();
+
+        let path = path == self.path || (path.starts_with(&self.path)
+            && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')));
+
+        let secure = !self.secure || uri.scheme_str() == Some("https");
+
+        domain && path && secure
+    }
+
+    fn same(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+// what the site remembers a logged in user by;
+// the same account works for exhentai too
+pub(super) fn member(member_id: &str, pass_hash: &str) -> Vec<Cookie> {
+    ["e-hentai.org", "exhentai.org"]
+        .iter()
+        .flat_map(|domain| vec![
+            Cookie::new(domain, "ipb_member_id", member_id),
+            Cookie::new(domain, "ipb_pass_hash", pass_hash),
+        ])
+        .collect()
+}
+
+// whether `host` is a subdomain of `domain`
+fn within(host: &str, domain: &str) -> bool {
+    host.ends_with(domain) && host[..(host.len() - domain.len())].ends_with('.')
+}
+
+// "Expires" comes in a few different formats in the wild; e.g. the site
+// sends "Sat, 18-Oct-2027 12:00:00 GMT", which isn't what RFC 7231 says
+fn parse_expires(value: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(value)
+        .or_else(|_| httpdate::parse_http_date(&value.replace('-', " ")))
+        .ok()
+}
+
+// the default path is the "directory" of the request path
+fn default_path(uri: &Uri) -> String {
+    let path = uri.path();
+
+    match path.rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(pos) => path[..pos].to_owned(),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add a cookie, replacing the one with the same name, domain and path
+    pub fn insert(&mut self, cookie: Cookie) {
+        self.cookies.retain(|old| !old.same(&cookie));
+
+        if !cookie.is_expired(SystemTime::now()) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    // the cookie named `name` that is sent to `host`
+    pub fn get(&self, host: &str, name: &str) -> Option<&Cookie> {
+        let now = SystemTime::now();
+
+        self.cookies
+            .iter()
+            .find(|cookie| cookie.name == name && !cookie.is_expired(now) && cookie.belongs(host))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        self.cookies.iter()
+    }
+
+    // remember a "Set-Cookie" header received from `uri`;
+    // malformed ones or ones for another domain are ignored
+    pub(super) fn store(&mut self, uri: &Uri, header: &str) {
+        let mut parts = header.split(';');
+
+        let (name, value) = match parts.next().and_then(|pair| pair.split_once('=')) {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return,
+        };
+
+        let host = uri.host().unwrap_or_default().to_ascii_lowercase();
+        let mut cookie = Cookie::new(&host, name, value);
+        cookie.host_only = true;
+        cookie.path = default_path(uri);
+
+        let mut max_age = None;
+
+        for attr in parts {
+            let (key, value) = match attr.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attr.trim(), ""),
+            };
+
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+
+                    // a server can't set cookies for other sites, nor for
+                    // a whole top-level domain (e.g. "org"); the host itself
+                    // may name a single label (e.g. "localhost") as before
+                    if host == domain && !domain.contains('.') {
+                        continue;
+                    }
+
+                    if !domain.contains('.') || !(host == domain || within(&host, &domain)) {
+                        return;
+                    }
+
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                },
+                "path" if value.starts_with('/') => cookie.path = value.to_owned(),
+                "expires" => cookie.expires = parse_expires(value),
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "secure" => cookie.secure = true,
+                _ => {},
+            }
+        }
+
+        // Max-Age wins over Expires; one too far in the future to
+        // be represented is taken as never expiring
+        if let Some(secs) = max_age {
+            cookie.expires = if secs > 0 {
+                SystemTime::now().checked_add(Duration::from_secs(secs as u64))
+            } else {
+                Some(UNIX_EPOCH)
+            };
+        }
+
+        self.insert(cookie);
+    }
+
+    // value for the "Cookie" header of a request to `uri`
+    pub(super) fn header(&self, uri: &Uri) -> Option<String> {
+        let now = SystemTime::now();
+
+        let mut cookies = self.cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(uri))
+            .collect::<Vec<_>>();
+
+        // longer paths first, as browsers do
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+        let header = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Some(header).filter(|header| !header.is_empty())
+    }
+
+    // read a jar saved with save(), which is in the Netscape cookies.txt
+    // format; so a file exported from a browser works as well
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut jar = Self::new();
+
+        for line in text.lines() {
+            // curl marks HttpOnly cookies like this
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields = line.split('\t').collect::<Vec<_>>();
+
+            if fields.len() != 7 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed cookie line: {:?}", line)
+                ));
+            }
+
+            let expires = fields[4].parse::<u64>().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed cookie expiry: {:?}", fields[4])
+            ))?;
+
+            let mut cookie = Cookie::new(fields[0], fields[5], fields[6]);
+            cookie.host_only = fields[1] != "TRUE";
+            cookie.path = fields[2].to_owned();
+            cookie.secure = fields[3] == "TRUE";
+            // same as Max-Age, an expiry out of range never comes
+            cookie.expires = match expires {
+                0 => None,
+                secs => UNIX_EPOCH.checked_add(Duration::from_secs(secs)),
+            };
+
+            jar.insert(cookie);
+        }
+
+        Ok(jar)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut text = String::from("# Netscape HTTP Cookie File\n");
+
+        for cookie in self.cookies.iter().filter(|cookie| !cookie.is_expired(now)) {
+            let expires = cookie.expires
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+
+            let domain = if cookie.host_only {
+                cookie.domain.clone()
+            } else {
+                format!(".{}", cookie.domain)
+            };
+
+            let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+
+            text.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                domain, flag(!cookie.host_only), cookie.path,
+                flag(cookie.secure), expires, cookie.name, cookie.value
+            ));
+        }
+
+        fs::write(path, text)
+    }
+}
@@ -0,0 +1,239 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hyper::{Uri, StatusCode};
+use futures_util::StreamExt;
+use hyper::header::{HeaderMap, HeaderValue, IF_RANGE, RANGE};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::Error;
+use super::client::Client;
+use super::integrity::Checksum;
+use super::stream::ImageStream;
+
+// what we know about the partially downloaded file,
+// kept next to it as "<file>.part.meta"
+#[derive(Default)]
+struct PartMeta {
+    etag: Option<String>,
+    length: Option<u64>,
+}
+
+impl PartMeta {
+    async fn load(path: &Path) -> Self {
+        let text = fs::read_to_string(path).await.unwrap_or_default();
+        let mut lines = text.lines();
+
+        let etag = lines
+            .next()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned);
+
+        let length = lines
+            .next()
+            .and_then(|line| line.parse().ok());
+
+        Self { etag, length }
+    }
+
+    async fn save(&self, path: &Path) -> io::Result<()> {
+        let text = format!(
+            "{}\n{}\n",
+            self.etag.as_deref().unwrap_or_default(),
+            self.length.map(|n| n.to_string()).unwrap_or_default()
+        );
+
+        fs::write(path, text).await
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// move the finished download to where it belongs
+async fn finish(part: &Path, meta: &Path, path: &Path) -> Result<(), Error> {
+    fs::rename(part, path).await?;
+
+    // it doesn't matter if it's already gone
+    let _ = fs::remove_file(meta).await;
+    Ok(())
+}
+
+// start over, throwing away what we had
+async fn discard(part: &Path, meta: &Path) {
+    let _ = fs::remove_file(part).await;
+    let _ = fs::remove_file(meta).await;
+}
+
+// hash what an earlier attempt left, to carry on from there;
+// images are small enough that reading them over is cheap
+async fn rehash(part: &Path, expected: &str) -> io::Result<Checksum> {
+    let mut file = fs::File::open(part).await?;
+    let mut checksum = Checksum::new(expected);
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buf).await? {
+            0 => return Ok(checksum),
+            n => checksum.update(&buf[..n]),
+        }
+    }
+}
+
+
+// a part which turns out to be wrong is of no use for resuming either
+async fn check(part: &Path, meta: &Path, checksum: Option<Checksum>) -> Result<(), Error> {
+    let res = checksum.map_or(Ok(()), Checksum::finish);
+
+    if res.is_err() {
+        discard(part, meta).await;
+    }
+
+    res
+}
+
+// download `dest` into `path` through "<path>.part", continuing
+// a previous attempt if the server supports range requests;
+// the whole file is checked against `hash` before it's moved in place,
+// hashed as it's written; only what an earlier attempt left is read over
+pub(super) async fn resume(
+    client: &Client,
+    dest: Uri,
+    referer: &str,
+    path: &Path,
+    hash: Option<&str>
+) -> Result<u64, Error> {
+    let part = with_suffix(path, ".part");
+    let meta_path = with_suffix(path, ".part.meta");
+
+    let mut offset = fs::metadata(&part).await.map_or(0, |meta| meta.len());
+    let meta = if offset > 0 {
+        PartMeta::load(&meta_path).await
+    } else {
+        PartMeta::default()
+    };
+
+    // we've got everything already, but failed to rename it last time
+    if offset > 0 && meta.length == Some(offset) {
+        let checksum = match hash {
+            Some(hash) => Some(rehash(&part, hash).await?),
+            None => None,
+        };
+
+        check(&part, &meta_path, checksum).await?;
+        finish(&part, &meta_path, path).await?;
+        return Ok(offset);
+    }
+
+    let stream = loop {
+        let mut headers = HeaderMap::new();
+
+        if offset > 0 {
+            headers.insert(RANGE, HeaderValue::from_str(&format!("bytes={}-", offset))?);
+
+            // a weak etag can't be used to validate ranges
+            let etag = meta.etag
+                .as_deref()
+                .filter(|etag| !etag.starts_with("W/"))
+                .and_then(|etag| HeaderValue::from_str(etag).ok());
+
+            // if the image changed since, the server sends the whole new one
+            if let Some(etag) = etag {
+                headers.insert(IF_RANGE, etag);
+            }
+        }
+
+        let stream = match client.open_image(dest.clone(), referer, headers).await {
+            // what we have doesn't match the image anymore
+            Err(Error::Status(StatusCode::RANGE_NOT_SATISFIABLE)) if offset > 0 => None,
+            res => Some(res?),
+        };
+
+        // the server may ignore the range and send the whole image (200),
+        // which is fine; but a range other than we asked is not
+        let valid = stream.as_ref().map_or(false, |stream| match stream.range {
+            Some((start, total)) => start == offset
+                && (meta.length.is_none() || total.is_none() || total == meta.length),
+            None => true,
+        });
+
+        match stream {
+            Some(stream) if valid => break stream,
+            // we didn't even ask for a range
+            Some(_) if offset == 0 => return Err(Error::Status(StatusCode::PARTIAL_CONTENT)),
+            _ => {
+                discard(&part, &meta_path).await;
+                offset = 0;
+            },
+        }
+    };
+
+    let (file, length) = match stream.range {
+        Some((_, total)) if offset > 0 => {
+            let file = OpenOptions::new().append(true).open(&part).await?;
+            (file, total.or(meta.length))
+        },
+        _ => {
+            offset = 0;
+            (fs::File::create(&part).await?, stream.content_length())
+        },
+    };
+
+    let mut checksum = match hash {
+        Some(hash) if offset > 0 => Some(rehash(&part, hash).await?),
+        Some(hash) => Some(Checksum::new(hash)),
+        None => None,
+    };
+
+    let meta = PartMeta { etag: stream.etag.clone(), length };
+    meta.save(&meta_path).await?;
+
+    // if this fails in the middle, the part stays for the next attempt
+    let written = offset + write(stream, file, checksum.as_mut()).await?;
+
+    if let Some(length) = length {
+        if written != length {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("downloaded {} bytes out of {}", written, length)
+            )));
+        }
+    }
+
+    check(&part, &meta_path, checksum).await?;
+    finish(&part, &meta_path, path).await?;
+    Ok(written)
+}
+
+// like ImageStream::write_to(), hashing what's actually written
+async fn write(mut stream: ImageStream, mut file: fs::File, mut checksum: Option<&mut Checksum>)
+    -> Result<u64, Error> {
+    let mut written = 0;
+
+    let res = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+
+            if let Some(checksum) = checksum.as_mut() {
+                checksum.update(&chunk);
+            }
+
+            written += chunk.len() as u64;
+        }
+
+        Ok::<_, Error>(())
+    }.await;
+
+    // what was written has to be in the file for the next attempt
+    file.flush().await?;
+    res.map(|_| written)
+}
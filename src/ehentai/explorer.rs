@@ -3,14 +3,27 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::str;
+use std::path::Path;
 use std::sync::Arc;
-use std::error::Error;
+use std::time::Duration;
 
-use super::client::Client;
+use hyper::Uri;
+
+use super::client::{Client, ClientBuilder, HttpVersion, Warning};
 use super::article::Article;
 use super::page::Page;
+use super::retry::RetryPolicy;
+use super::limit::RateLimit;
+use super::cookie::CookieJar;
+use super::proxy::Proxy;
+use super::identity::{Identity, IdentityStats};
+use super::transport::Transport;
+use super::cache::CachePolicy;
+use super::account::{self, AccountStatus};
+use super::parser;
+use crate::Error;
 
-type ErrorBox = Box<dyn Error>;
+const LOGIN_URL: &str = "https://forums.e-hentai.org/index.php?act=Login&CODE=01";
 
 #[derive(Clone)]
 pub struct Explorer {
@@ -27,7 +40,7 @@ impl Explorer {
     }
 
     pub fn with_cookies(member_id: &str, pass_hash: &str) -> Self {
-        let mut client = Client::new();
+        let client = Client::new();
         client.set_cookies(member_id, pass_hash);
 
         Self {
@@ -35,12 +48,294 @@ impl Explorer {
         }
     }
 
+    // browse exhentai instead, which needs an account allowed in;
+    // the igneous cookie is picked up on the first visit
+    pub fn exhentai(member_id: &str, pass_hash: &str) -> Self {
+        // the default configuration is always valid
+        Self::builder()
+            .exhentai(true)
+            .cookies(member_id, pass_hash)
+            .build()
+            .unwrap()
+    }
+
+    // log in with a forums account; see ExplorerBuilder::login()
+    // to log in with other settings than the default
+    pub async fn login(username: &str, password: &str) -> Result<Self, Error> {
+        Self::builder().login(username, password).await
+    }
+
+    pub fn builder() -> ExplorerBuilder {
+        ExplorerBuilder::new()
+    }
+
+    // the cookies of this session, including the ones the site has set
+    pub fn cookies(&self) -> CookieJar {
+        self.client.cookies()
+    }
+
+    // save the session so that ExplorerBuilder::cookie_jar() can pick it
+    // up later, without logging in again
+    pub fn save_cookies(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(self.client.cookies().save(path)?)
+    }
+
+    // how each identity of the pool has been doing
+    pub fn identities(&self) -> Vec<IdentityStats> {
+        self.client.identities()
+    }
+
     pub fn search(&self, keyword: &str) -> Page {
         Page::new(self.client.clone(), 0, keyword)
     }
 
     pub async fn article_from_path(&self, path: String)
-        -> Result<Article, ErrorBox> {
-        Article::new(self.client.clone(), path).await
+        -> Result<Article, Error> {
+        Article::new(self.client.clone(), path, false).await
+    }
+
+    // same as article_from_path(), but never from the cache; so are
+    // the pages the article loads later, see Article::bypass_cache()
+    pub async fn fresh_article_from_path(&self, path: String)
+        -> Result<Article, Error> {
+        Article::new(self.client.clone(), path, true).await
+    }
+
+    // how much of the image limit is used, so that bulk downloads can
+    // stop before running out; members get their balances and perks too
+    pub async fn account_status(&self) -> Result<AccountStatus, Error> {
+        account::status(&self.client).await
+    }
+}
+
+// configures the networking stack shared by everything an Explorer gives
+#[derive(Default)]
+pub struct ExplorerBuilder {
+    client: ClientBuilder,
+    login_url: Option<String>,
+}
+
+impl ExplorerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // how long to wait for a connection to be established
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.connect_timeout(timeout);
+        self
+    }
+
+    // how long to wait for the response, or the next chunk of its body;
+    // 30 seconds by default, after which a stalled image server is
+    // given up on for another one (see failover())
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.read_timeout(timeout);
+        self
+    }
+
+    // how long a single attempt of a request may take in total
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.total_timeout(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, agent: &str) -> Self {
+        self.client = self.client.user_agent(agent);
+        self
+    }
+
+    // sent with every request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.client = self.client.header(name, value);
+        self
+    }
+
+    // HTTP/2 needs detour(false); build() fails otherwise
+    pub fn http_version(mut self, version: HttpVersion) -> Self {
+        self.client = self.client.http_version(version);
+        self
+    }
+
+    // whether to connect through detour to bypass DPI censorships
+    // (enabled by default); plain TLS is used otherwise
+    pub fn detour(mut self, enabled: bool) -> Self {
+        self.client = self.client.detour(enabled);
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.client = self.client.retry(policy);
+        self
+    }
+
+    pub fn rate_limits(mut self, pages: RateLimit, images: RateLimit) -> Self {
+        self.client = self.client.rate_limits(pages, images);
+        self
+    }
+
+    // how many requests may be in flight at once
+    pub fn max_concurrent(mut self, n: usize) -> Self {
+        self.client = self.client.max_concurrent(n);
+        self
+    }
+
+    pub fn cookies(mut self, member_id: &str, pass_hash: &str) -> Self {
+        self.client = self.client.cookies(member_id, pass_hash);
+        self
+    }
+
+    // browse exhentai instead of e-hentai; see Explorer::exhentai()
+    pub fn exhentai(mut self, enabled: bool) -> Self {
+        self.client = self.client.exhentai(enabled);
+        self
+    }
+
+    // some accounts need the igneous cookie from a browser to get in
+    pub fn igneous(mut self, value: &str) -> Self {
+        self.client = self.client.igneous(value);
+        self
+    }
+
+    // where searches go, instead of the front page of the site
+    // (e.g. "https://e-hentai.org/"); mainly for testing
+    pub fn base_url(mut self, url: &str) -> Self {
+        self.client = self.client.base_url(url);
+        self
+    }
+
+    // connect to everything through `proxy`; composes with detour,
+    // which then works on the tunnel the proxy opens
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.client = self.client.proxy(proxy);
+        self
+    }
+
+    // the proxy for the site itself (pages, searches and so on);
+    // None to connect directly
+    pub fn page_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.client = self.client.page_proxy(proxy);
+        self
+    }
+
+    // the proxy for image servers (and thumbnails);
+    // None to connect directly
+    pub fn image_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.client = self.client.image_proxy(proxy);
+        self
+    }
+
+    // add an identity to the pool to rotate between; one which gets
+    // banned, runs out of quota or is logged out is put aside until
+    // the ban expires (or for retire_for() if we can't tell)
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.client = self.client.identity(identity);
+        self
+    }
+
+    // how long to put an identity aside for, unless the site tells
+    // (default: an hour)
+    pub fn retire_for(mut self, duration: Duration) -> Self {
+        self.client = self.client.retire_for(duration);
+        self
+    }
+
+    // send requests through `transport` instead of the network,
+    // e.g. a MemoryTransport in tests
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.client = self.client.transport(transport);
+        self
+    }
+
+    // save every response into `dir`, to be replayed later;
+    // an existing fixture of the same request is overwritten
+    pub fn record(mut self, dir: impl AsRef<Path>) -> Self {
+        self.client = self.client.record(dir);
+        self
+    }
+
+    // answer every request with what was recorded into `dir`,
+    // without touching the network; a request never recorded fails
+    pub fn replay(mut self, dir: impl AsRef<Path>) -> Self {
+        self.client = self.client.replay(dir);
+        self
+    }
+
+    // keep the pages fetched on disk, so they're not fetched again
+    // while still fresh; images aren't kept
+    pub fn cache(mut self, policy: CachePolicy) -> Self {
+        self.client = self.client.cache(policy);
+        self
+    }
+
+    // when an image server fails, ask the image page for another one
+    // this many times before giving up (default: 2)
+    pub fn failover(mut self, hops: u32) -> Self {
+        self.client = self.client.failover(hops);
+        self
+    }
+
+    // e.g. CookieJar::load() of a file from Explorer::save_cookies()
+    pub fn cookie_jar(mut self, jar: CookieJar) -> Self {
+        self.client = self.client.cookie_jar(jar);
+        self
+    }
+
+    // called when something is off but not worth failing for,
+    // e.g. a page with bytes invalid in its charset
+    pub fn on_warning<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Warning) + Send + Sync + 'static,
+    {
+        self.client = self.client.on_warning(Arc::new(hook));
+        self
+    }
+
+    // where login() posts the form to, instead of the forums
+    pub fn login_url(mut self, url: &str) -> Self {
+        self.login_url = Some(url.to_owned());
+        self
+    }
+
+    pub fn build(self) -> Result<Explorer, Error> {
+        Ok(Explorer {
+            client: Arc::new(self.client.build()?),
+        })
+    }
+
+    // build, then log in with a forums account; the member cookies
+    // can be kept with Explorer::save_cookies() for later sessions.
+    // with identities, the first one logs in and every one gets the cookies
+    pub async fn login(self, username: &str, password: &str) -> Result<Explorer, Error> {
+        let url = self.login_url.as_deref().unwrap_or(LOGIN_URL).parse::<Uri>()?;
+        let host = url.host().unwrap_or_default().to_owned();
+        let explorer = self.build()?;
+
+        let doc = explorer.client.post_form(url, &[
+            ("referer", "https://forums.e-hentai.org/index.php"),
+            ("b", ""),
+            ("bt", ""),
+            ("UserName", username),
+            ("PassWord", password),
+            // remember me
+            ("CookieDate", "1"),
+        ]).await?;
+
+        if let Some(err) = parser::login_error(&doc) {
+            return Err(err);
+        }
+
+        let jar = explorer.client.cookies();
+
+        // guests get "0" as their id
+        match (jar.get(&host, "ipb_member_id"), jar.get(&host, "ipb_pass_hash")) {
+            (Some(id), Some(hash)) if id.value() != "0" && !hash.value().is_empty() => {
+                // the forums set them for the whole site, but make sure
+                // they reach the galleries wherever they came from
+                explorer.client.set_cookies(id.value(), hash.value());
+                Ok(explorer)
+            },
+            _ => Err(Error::LoginFailed(String::from("no member cookies were given"))),
+        }
     }
 }
@@ -0,0 +1,122 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+use super::cookie::{self, CookieJar};
+use super::proxy::Proxy;
+
+// who we make requests as: where they come from and which account;
+// a client may rotate between several of them
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub(super) name: String,
+    pub(super) proxy: Option<Proxy>,
+    pub(super) cookies: CookieJar,
+}
+
+impl Identity {
+    // `name` is only for telling them apart in IdentityStats
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            proxy: None,
+            cookies: CookieJar::new(),
+        }
+    }
+
+    // used for every host; without one, the client's proxies are used
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn cookies(mut self, member_id: &str, pass_hash: &str) -> Self {
+        for cookie in cookie::member(member_id, pass_hash) {
+            self.cookies.insert(cookie);
+        }
+
+        self
+    }
+
+    pub fn cookie_jar(mut self, jar: CookieJar) -> Self {
+        for cookie in jar.iter() {
+            self.cookies.insert(cookie.clone());
+        }
+
+        self
+    }
+}
+
+// a snapshot of how an identity has been doing
+#[derive(Debug, Clone)]
+pub struct IdentityStats {
+    pub name: String,
+    // requests made as this identity
+    pub requests: u64,
+    // how many times it got banned, ran out of quota or was logged out
+    pub retirements: u64,
+    // how long until it's used again, if it's retired now
+    pub retired_for: Option<Duration>,
+}
+
+// longer bans are taken for this long, which is as good as forever
+// for a client but keeps the instant in range
+const MAX_RETIREMENT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Default)]
+pub(super) struct Usage {
+    requests: AtomicU64,
+    retirements: AtomicU64,
+    retired_until: Mutex<Option<Instant>>,
+}
+
+impl Usage {
+    pub(super) fn used(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn retire(&self, duration: Duration) {
+        self.retirements.fetch_add(1, Ordering::Relaxed);
+
+        let until = Instant::now() + duration.min(MAX_RETIREMENT);
+        let mut retired = self.retired_until.lock().unwrap();
+
+        // another request may have learned of a longer ban already
+        if retired.map_or(true, |old| old < until) {
+            *retired = Some(until);
+        }
+    }
+
+    // None if it can be used now
+    pub(super) fn retired_for(&self, now: Instant) -> Option<Duration> {
+        self.retired_until
+            .lock()
+            .unwrap()
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    pub(super) fn stats(&self, name: &str) -> IdentityStats {
+        IdentityStats {
+            name: name.to_owned(),
+            requests: self.requests.load(Ordering::Relaxed),
+            retirements: self.retirements.load(Ordering::Relaxed),
+            retired_for: self.retired_for(Instant::now()),
+        }
+    }
+}
+
+// how long an identity should rest after running into `err`, if at all;
+// `default` is for when the site doesn't tell
+pub(super) fn retirement(err: &Error, default: Duration) -> Option<Duration> {
+    match err.inner() {
+        Error::Banned(Some(expiry)) => Some(*expiry),
+        Error::Banned(None) | Error::QuotaExceeded | Error::Unauthorized => Some(default),
+        _ => None,
+    }
+}
@@ -0,0 +1,61 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use sha1::{Digest, Sha1};
+use crate::Error;
+
+// links to image pages look like "/s/3a1b2c3d4e/1088955-1",
+// where the first part is the beginning of SHA-1 of the original image
+pub(super) fn expected_hash(page: &str) -> Option<String> {
+    let (_, rest) = page.split_once("/s/")?;
+    let hash = rest.split('/').next()?;
+
+    if hash.len() == 10 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        Some(hash.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+// hashes an image as it arrives, to be checked once it's all there
+pub(super) struct Checksum {
+    expected: String,
+    sha1: Sha1,
+}
+
+impl Checksum {
+    pub(super) fn new(expected: &str) -> Self {
+        Self {
+            expected: expected.to_owned(),
+            sha1: Sha1::new(),
+        }
+    }
+
+    pub(super) fn update(&mut self, data: &[u8]) {
+        self.sha1.update(data);
+    }
+
+    pub(super) fn finish(self) -> Result<(), Error> {
+        let actual = self.sha1
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        if actual.starts_with(&self.expected) {
+            Ok(())
+        } else {
+            Err(Error::Integrity {
+                expected: self.expected,
+                actual,
+            })
+        }
+    }
+}
+
+pub(super) fn verify(expected: &str, data: &[u8]) -> Result<(), Error> {
+    let mut checksum = Checksum::new(expected);
+    checksum.update(data);
+    checksum.finish()
+}
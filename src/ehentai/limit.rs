@@ -0,0 +1,139 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Semaphore, OwnedSemaphorePermit};
+use tokio::time::{sleep, Instant};
+
+// a request per hour; anything slower is taken as this, so that
+// the wait for the next token stays within reason
+const MIN_RATE: f64 = 1.0 / 3600.0;
+
+// buckets are looked through for idle ones once there are this many
+const PRUNE_AT: usize = 64;
+
+// how many requests a single host may receive
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: u32,
+}
+
+impl RateLimit {
+    // allow `per_second` requests per second on average,
+    // and at most `burst` requests at once after being idle;
+    // at least one request an hour is always allowed
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second: per_second.max(MIN_RATE),
+            burst: burst.max(1),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(f64::INFINITY, u32::MAX)
+    }
+}
+
+// a token bucket for each host
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub(super) struct Limiter {
+    pages: RateLimit,
+    images: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Limiter {
+    pub(super) fn new(pages: RateLimit, images: RateLimit, max_concurrent: usize) -> Self {
+        Self {
+            pages,
+            images,
+            buckets: Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    // pages are what the site counts for bans; images come from
+    // H@H servers all around the world which have their own limits
+    fn limit(&self, host: &str) -> RateLimit {
+        if is_page_host(host) {
+            self.pages
+        } else {
+            self.images
+        }
+    }
+
+    // take a token for the host, waiting until one is available
+    async fn take(&self, host: &str) {
+        let limit = self.limit(host);
+
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = Instant::now();
+
+                if buckets.len() >= PRUNE_AT {
+                    self.prune(&mut buckets, now);
+                }
+
+                let bucket = buckets
+                    .entry(host.to_owned())
+                    .or_insert_with(|| Bucket {
+                        tokens: limit.burst as f64,
+                        last: now,
+                    });
+
+                let elapsed = now.duration_since(bucket.last).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * limit.per_second)
+                    .min(limit.burst as f64);
+                bucket.last = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                ((1.0 - bucket.tokens) / limit.per_second).min(1.0 / MIN_RATE)
+            };
+
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    // images come from a new host every now and then; a bucket left
+    // alone long enough to fill up is no different from a new one
+    fn prune(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|host, bucket| {
+            let limit = self.limit(host);
+            let elapsed = now.duration_since(bucket.last).as_secs_f64();
+
+            bucket.tokens + elapsed * limit.per_second < limit.burst as f64
+        });
+    }
+
+    #[cfg(test)]
+    pub(super) fn hosts(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    // wait for our turn to send a request to the host;
+    // keep the permit until the response is fully read
+    pub(super) async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        self.take(host).await;
+
+        // the semaphore is never closed
+        self.permits.clone().acquire_owned().await.unwrap()
+    }
+}
+
+pub(super) fn is_page_host(host: &str) -> bool {
+    host == "e-hentai.org" || host.ends_with(".e-hentai.org") || host == "exhentai.org"
+}
@@ -7,11 +7,37 @@ mod page;
 mod article;
 mod parser;
 mod client;
+mod retry;
+mod limit;
+mod charset;
+mod stream;
+mod download;
+mod cookie;
+mod proxy;
+mod identity;
+mod transport;
+mod record;
+mod cache;
+mod coalesce;
+mod integrity;
+mod account;
 mod explorer;
 
 pub use tag::{ParseTagError, TagKind, Tag, TagMap, ArticleKind};
-pub use article::{Draft, Comment, Article};
-pub use explorer::{Explorer};
+pub use article::{Draft, Comment, Article, ImagePage, OriginalPolicy};
+pub use explorer::{Explorer, ExplorerBuilder};
+pub use client::{HttpVersion, Warning};
+pub use parser::ParseError;
+pub use retry::RetryPolicy;
+pub use limit::RateLimit;
+pub use stream::ImageStream;
+pub use cookie::{Cookie, CookieJar};
+pub use proxy::Proxy;
+pub use identity::{Identity, IdentityStats};
+pub use transport::{BoxFuture, Transport, MemoryTransport};
+pub use record::{RecordTransport, ReplayTransport};
+pub use cache::{CachePolicy, PageKind};
+pub use account::{AccountStatus, ImageLimit};
 
 #[cfg(test)]
 mod tests;
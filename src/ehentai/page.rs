@@ -3,14 +3,13 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::sync::Arc;
-use std::error::Error;
 use hyper::Uri;
+use hyper::http;
 
 use super::client::Client;
 use super::article::Draft;
 use super::parser;
-
-type ErrorBox = Box<dyn Error>;
+use crate::Error;
 
 fn percent_encode(from: &str) -> String {
     let mut res = String::new();
@@ -37,6 +36,7 @@ pub struct Page {
     results: Option<usize>,
     limit: Option<usize>,
     query: String,
+    bypass_cache: bool,
 }
 
 impl Page {
@@ -49,14 +49,18 @@ impl Page {
             results: None,
             limit: None,
             query,
+            bypass_cache: false,
         }
     }
 
-    fn uri(&self) -> Result<Uri, impl Error> {
+    fn uri(&self) -> Result<Uri, http::Error> {
+        let base = self.client.base();
+        let path = base.path().trim_end_matches('/');
+
         Uri::builder()
-            .scheme("https")
-            .authority("e-hentai.org")
-            .path_and_query(format!("?page={}&{}", self.page, self.query))
+            .scheme(base.scheme_str().unwrap_or("https"))
+            .authority(base.authority().map_or("e-hentai.org", |auth| auth.as_str()))
+            .path_and_query(format!("{}/?page={}&{}", path, self.page, self.query))
             .build()
     }
 
@@ -71,8 +75,7 @@ impl Page {
         if let Some(lim) = self.limit {
             Some(lim)
         } else if let Some(n) = self.results {
-            // self.results must not be 0
-            Some((n - 1) / ARTICLES_PER_PAGE + 1)
+            Some((n + ARTICLES_PER_PAGE - 1) / ARTICLES_PER_PAGE)
         } else {
             None
         }
@@ -93,19 +96,27 @@ impl Page {
         self
     }
 
-    pub async fn next(&mut self) -> Result<Option<Vec<Draft>>, ErrorBox> {
+    // always fetch the pages, even if they're in the cache
+    pub fn bypass_cache(mut self, bypass: bool) -> Self {
+        self.bypass_cache = bypass;
+        self
+    }
+
+    pub async fn next(&mut self) -> Result<Option<Vec<Draft>>, Error> {
         if self.len().filter(|len| len <= &self.page).is_some() {
             return Ok(None);
         }
 
-        let doc = self.client.get_html(self.uri()?).await?;
+        let doc = self.client
+            .load_html(self.uri()?, self.client.retry(), self.bypass_cache)
+            .await?;
         self.page += 1;
         self.results = Some(parser::search_results(&doc)?);
 
         if let Some(list) = parser::article_list(&doc)? {
             let list = list
                 .into_iter()
-                .map(|meta| Draft::new(self.client.clone(), meta))
+                .map(|meta| Draft::new(self.client.clone(), meta, self.bypass_cache))
                 .collect();
             
             Ok(Some(list))
@@ -2,44 +2,176 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::fmt;
 use std::error::Error;
-use std::num::ParseIntError;
 use select::document::Document;
 use select::node::Node;
-use select::predicate::{Predicate, Attr, Class, Name};
-use super::article::{DraftMeta, ArticleMeta, Vote, Comment};
-use super::tag::{ParseTagError, TagKind, Tag, TagMap, ArticleKind};
+use select::predicate::{Predicate, Attr, Class, Name, Text};
+use super::article::{DraftMeta, ArticleMeta, Vote, Comment, ImagePage};
+use super::account::ImageLimit;
+use super::tag::{TagKind, Tag, TagMap, ArticleKind};
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    context: String,
+    path: Option<String>,
+}
+
+impl ParseError {
+    pub(super) fn new(context: impl Into<String>) -> Self {
+        Self {
+            context: context.into(),
+            path: None,
+        }
+    }
+
+    // remember which page we were parsing, unless we already know it
+    pub(super) fn at(mut self, path: &str) -> Self {
+        if self.path.is_none() {
+            self.path = Some(path.to_owned());
+        }
+
+        self
+    }
+
+    // what the parser was looking at when it failed,
+    // e.g. `#gdd row 6 (Length): expected "N pages"`
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    // path of the page that failed to parse, if known
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "Failed to parse {}: {}", path, self.context),
+            None => write!(f, "Failed to parse page: {}", self.context),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+// the markup of the site changes from time to time; instead of unwrapping
+// every step, turn a missing node (or a malformed value) into a ParseError
+// describing what we expected to see there
+trait Context<T> {
+    fn context(self, what: &str) -> Result<T, ParseError>;
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, what: &str) -> Result<T, ParseError> {
+        self.ok_or_else(|| ParseError::new(what))
+    }
+}
+
+impl<T, E: fmt::Display> Context<T> for Result<T, E> {
+    fn context(self, what: &str) -> Result<T, ParseError> {
+        self.map_err(|err| ParseError::new(format!("{} ({})", what, err)))
+    }
+}
+
+// take any document, return the reason given by the site
+// if it's the notice shown in place of a removed (or expunged) gallery
+pub fn unavailable(doc: &Document) -> Option<String> {
+    let title = doc.find(Name("title")).next()?.text();
+
+    if !title.starts_with("Gallery Not Available") {
+        return None;
+    }
+
+    // the notice is like "This gallery has been removed or is unavailable."
+    let reason = doc
+        .find(Class("d"))
+        .next()
+        .map(|node| node.text().trim().to_owned())
+        .filter(|text| !text.is_empty())
+        .unwrap_or_else(|| String::from("Gallery is not available"));
+
+    Some(reason)
+}
+
+// take the page the forums answer a login with, return why it failed
+// if it did; failures are shown in a box like
+// "The error returned was: Username or password incorrect"
+pub fn login_error(doc: &Document) -> Option<crate::Error> {
+    let captcha = doc
+        .find(Class("g-recaptcha").or(Class("h-captcha")).or(Attr("id", "recaptcha_widget")))
+        .next()
+        .is_some();
+
+    let message = doc
+        .find(Class("errorwrap"))
+        .next()
+        .map(|node| match node.find(Name("p")).next() {
+            Some(p) => p.text(),
+            None => node.text(),
+        })
+        .map(|text| text.trim().to_owned());
+
+    let lower = message.as_deref().unwrap_or_default().to_lowercase();
+
+    // the form may come back with a captcha after a wrong password too
+    if lower.contains("captcha") {
+        Some(crate::Error::CaptchaRequired)
+    } else if lower.contains("password incorrect") || lower.contains("no such member") {
+        Some(crate::Error::WrongCredentials)
+    } else if captcha {
+        Some(crate::Error::CaptchaRequired)
+    } else {
+        message.map(crate::Error::LoginFailed)
+    }
+}
 
 // take a document for an article list,
 // return total count of results of the list
-pub fn search_results(doc: &Document) -> Result<usize, Box<dyn Error>> {
-    Ok(doc.find(Class("ip"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
-        .as_text().unwrap() // this would be like "Showing 608,394 results"
-        .strip_prefix("Showing ").unwrap()
-        .strip_suffix(" results").unwrap()
+pub fn search_results(doc: &Document) -> Result<usize, ParseError> {
+    const WHAT: &str = ".ip: expected \"Showing N results\"";
+
+    let ip = match doc.find(Class("ip")).next() {
+        Some(ip) => ip,
+        // a search without hits has no counter at all
+        None if doc.find(Class("ido")).any(|node| node.text().contains("No hits found")) => {
+            return Ok(0);
+        },
+        None => return Err(ParseError::new(WHAT)),
+    };
+
+    ip.first_child().context(WHAT)?
+        .as_text().context(WHAT)? // this would be like "Showing 608,394 results"
+        .strip_prefix("Showing ").context(WHAT)?
+        .strip_suffix(" results").context(WHAT)?
         .replace(',', "") // rust's parse() doesn't understand thousands separators
-        .parse::<usize>()?)
+        .parse::<usize>().context(WHAT)
 }
 
 // take a document for a list page (e.g. search result),
 // return the list of the articles in the document
 pub fn article_list(doc: &Document)
-    -> Result<Option<Vec<DraftMeta>>, Box<dyn Error>> {
+    -> Result<Option<Vec<DraftMeta>>, ParseError> {
     let table = doc
         .find(Name("table").and(Class("gltc")))
-        .nth(0);
+        .next();
 
     // no hits found
-    if table.is_none() {
-        return Ok(None);
-    }
-
-    let table = table.unwrap().first_child().unwrap();
+    let table = match table {
+        Some(table) => table.first_child().context("table.gltc: expected rows")?,
+        None => return Ok(None),
+    };
 
     // requested invalid page
-    if table.children().nth(1).unwrap().first_child().unwrap().as_text().is_some() {
+    let invalid = table
+        .children()
+        .nth(1)
+        .and_then(|row| row.first_child())
+        .map_or(true, |node| node.as_text().is_some());
+
+    if invalid {
         return Ok(None);
     }
 
@@ -47,25 +179,28 @@ pub fn article_list(doc: &Document)
     let mut list = Vec::new();
 
     // the first element is header row; skip
-    for node in table.children().skip(1) {
+    for (i, node) in table.children().enumerate().skip(1) {
+        let what = |s: &str| format!("table.gltc row {}: expected {}", i, s);
+
         // advert!
-        if node.first_child().unwrap().attr("class") == Some("itd") {
+        let head = node.first_child().context(&what("a column"))?;
+        if head.attr("class") == Some("itd") {
             continue;
         }
 
         let mut iter = node.children();
 
         // 4 columns of the row in total
-        let first = iter.next().unwrap();
-        let second = iter.next().unwrap();
-        let third = iter.next().unwrap();
-        let fourth = iter.next().unwrap();
+        let first = iter.next().context(&what("1st column"))?;
+        let second = iter.next().context(&what("2nd column"))?;
+        let third = iter.next().context(&what("3rd column"))?;
+        let fourth = iter.next().context(&what("4th column"))?;
 
         // the first column contains category of the article
-        let kind = {
-            let text = first.first_child().unwrap().text();
-            text.parse::<ArticleKind>()?
-        };
+        let kind = first
+            .first_child().context(&what("category"))?
+            .text()
+            .parse::<ArticleKind>().context(&what("category"))?;
 
         // the second contains thumbnail, uploaded time,
         // rate, and download link (costing GP)
@@ -73,15 +208,15 @@ pub fn article_list(doc: &Document)
             let mut iter = second.children().skip(1);
 
             let thumb = iter
-                .next().unwrap()
+                .next().context(&what("thumbnail"))?
                 .find(Name("img"))
-                .nth(0).unwrap()
-                .attr("src").unwrap()
+                .next().context(&what("thumbnail <img>"))?
+                .attr("src").context(&what("thumbnail src"))?
                 .to_string();
 
             let posted = iter
-                .next().unwrap()
-                .first_child().unwrap()
+                .next().context(&what("posted date"))?
+                .first_child().context(&what("posted date"))?
                 .text();
 
             // TODO: i don't know how to get rate...
@@ -90,22 +225,26 @@ pub fn article_list(doc: &Document)
 
         // the third contains link, title, and tags
         let (path, title, tags) = {
-            let node = third.first_child().unwrap();
+            let node = third.first_child().context(&what("gallery link"))?;
 
             let path = node
-                .attr("href").unwrap()
+                .attr("href").context(&what("gallery href"))?
                 .to_string();
 
             let mut iter = node.children();
-            let title = iter.next().unwrap().text();
-            
+            let title = iter.next().context(&what("title"))?.text();
+
             // although only some of the tags are visible in a browser,
             // there are all the tags in html; the rest are just hidden
-            let tags = iter
-                .next().unwrap()
-                .children()
-                .map(|x| x.attr("title").ok_or(ParseTagError())?.parse::<Tag>())
-                .collect::<Result<TagMap, _>>()?;
+            let mut tags = TagMap::new();
+
+            for tag in iter.next().context(&what("tag list"))?.children() {
+                let tag = tag
+                    .attr("title").context(&what("tag title"))?
+                    .parse::<Tag>().context(&what("\"namespace:tag\""))?;
+
+                tags.add(tag);
+            }
 
             (path, title, tags)
         };
@@ -113,16 +252,16 @@ pub fn article_list(doc: &Document)
         // the fourth contains uploader name and number of pages in the article
         let (uploader, length) = {
             let uploader = fourth
-                .first_child().unwrap()
-                .first_child().unwrap()
+                .first_child().context(&what("uploader"))?
+                .first_child().context(&what("uploader"))?
                 .text();
 
             let length = fourth
-                .last_child().unwrap()
+                .last_child().context(&what("\"N pages\""))?
                 .text()
                 .split_ascii_whitespace()
-                .nth(0).unwrap()
-                .parse::<usize>()?;
+                .next().context(&what("\"N pages\""))?
+                .parse::<usize>().context(&what("\"N pages\""))?;
 
             (uploader, length)
         };
@@ -144,161 +283,200 @@ pub fn article_list(doc: &Document)
 
 // take a document of an article gallery, return information of the article
 //
-// NOTE: this function DOES NOT parse the image list. call parse_image_list() 
+// NOTE: this function DOES NOT parse the image list. call parse_image_list()
 // and change the article data accordingly to get the list of images.
 pub fn article(doc: &Document, path: String)
-    -> Result<ArticleMeta, Box<dyn Error>> {
+    -> Result<ArticleMeta, ParseError> {
+    gallery(doc, path.clone()).map_err(|err| err.at(&path))
+}
+
+fn gallery(doc: &Document, path: String)
+    -> Result<ArticleMeta, ParseError> {
     let (title, original_title) = {
-        let mut iter = doc.find(Attr("id", "gd2")).nth(0).unwrap().children();
-        
-        let title = iter.next().unwrap().text();
-        let orig = iter.next().unwrap().text();
+        let mut iter = doc
+            .find(Attr("id", "gd2"))
+            .next().context("#gd2: expected title")?
+            .children();
+
+        let title = iter.next().context("#gd2: expected title")?.text();
+        let orig = iter.next().context("#gd2: expected original title")?.text();
 
         (title, orig)
     };
 
     let thumb = {
+        const WHAT: &str = "#gd1: expected \"url(...)\" style";
+
         let text = doc
             .find(Attr("id", "gd1"))
-            .nth(0).unwrap()
-            .first_child().unwrap()
-            .attr("style").unwrap();
+            .next().context(WHAT)?
+            .first_child().context(WHAT)?
+            .attr("style").context(WHAT)?;
 
-        let begin = text.find("url(").unwrap();
-        let end = text.find(')').unwrap();
+        let begin = text.find("url(").context(WHAT)? + "url(".len();
+        let end = begin + text[begin..].find(')').context(WHAT)?;
 
-        text[(begin + "url(".len())..end].to_owned()
+        text[begin..end].to_owned()
     };
 
-    let kind = doc
-        .find(Attr("id", "gdc"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
-        .first_child().unwrap() // this should be a text node
-        .as_text().unwrap()
-        .parse::<ArticleKind>()?;
+    let kind = {
+        const WHAT: &str = "#gdc: expected category";
+
+        doc.find(Attr("id", "gdc"))
+            .next().context(WHAT)?
+            .first_child().context(WHAT)?
+            .first_child().context(WHAT)? // this should be a text node
+            .as_text().context(WHAT)?
+            .parse::<ArticleKind>().context(WHAT)?
+    };
 
     let uploader = doc
         .find(Attr("id", "gdn"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
+        .next().context("#gdn: expected uploader")?
+        .first_child().context("#gdn: expected uploader")?
         .text();
 
     // parse #gdd, which has most useful informations
     let mut iter = doc
         .find(Attr("id", "gdd"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
-        .first_child().unwrap()
+        .next().context("#gdd: expected table")?
+        .first_child().context("#gdd: expected table")?
+        .first_child().context("#gdd: expected table body")?
         .children();
 
-    let posted = iter
-        .next().unwrap()
-        .last_child().unwrap()
-        .text();
+    // each row of #gdd is "<td>label:</td><td>value</td>",
+    // so take the value cell of the next row
+    let mut row = 0;
+    let mut next_row = |label: &str| {
+        row += 1;
+        let what = format!("#gdd row {} ({}): expected value", row, label);
+
+        iter.next()
+            .and_then(|node| node.last_child())
+            .context(&what)
+    };
+
+    let posted = next_row("Posted")?.text();
 
     let parent = {
-        let node = iter
-            .next().unwrap()
-            .last_child().unwrap()
-            .first_child().unwrap();
+        let node = next_row("Parent")?
+            .first_child().context("#gdd row 2 (Parent): expected \"None\" or link")?;
 
         match node.as_text() {
             Some("None") => None,
-            None => Some(node.attr("href").unwrap().to_string()),
-            _ => unreachable!()
+            Some(_) => {
+                return Err(ParseError::new(
+                    "#gdd row 2 (Parent): expected \"None\" or link"
+                ));
+            },
+            None => Some(node
+                .attr("href").context("#gdd row 2 (Parent): expected href")?
+                .to_string()),
         }
     };
 
     // what is this for?
-    let visible = iter
-        .next().unwrap()
-        .last_child().unwrap()
-        .first_child().unwrap()
-        .as_text().unwrap() == "Yes";
+    let visible = next_row("Visible")?
+        .first_child()
+        .and_then(|node| node.as_text())
+        .context("#gdd row 3 (Visible): expected \"Yes\" or \"No\"")? == "Yes";
 
     let (language, translated) = {
-        let node = iter
-            .next().unwrap()
-            .last_child().unwrap();
+        let node = next_row("Language")?;
 
         let language = node
-            .first_child().unwrap()
-            .as_text().unwrap()
+            .first_child()
+            .and_then(|node| node.as_text())
+            .context("#gdd row 4 (Language): expected language")?
             .trim()
             .to_owned();
 
         let translated = node
-            .last_child().unwrap()
-            .name().is_some();
+            .last_child()
+            .map_or(false, |node| node.name().is_some());
 
         (language, translated)
     };
 
-    let file_size = iter
-        .next().unwrap()
-        .last_child().unwrap()
-        .text();
+    let file_size = next_row("File Size")?.text();
 
-    let length = iter
-        .next().unwrap()
-        .last_child().unwrap()
-        .first_child().unwrap()
-        .as_text().unwrap()
-        .strip_suffix(" pages").unwrap() // it seems there is no article with 1 page
-        .parse::<usize>()?;
+    let length = {
+        const WHAT: &str = "#gdd row 6 (Length): expected \"N pages\"";
+
+        next_row("Length")?
+            .first_child()
+            .and_then(|node| node.as_text())
+            .context(WHAT)?
+            .strip_suffix(" pages").context(WHAT)? // it seems there is no article with 1 page
+            .parse::<usize>().context(WHAT)?
+    };
 
     let favorited = {
-        let text = iter
-            .next().unwrap()
-            .last_child().unwrap()
-            .first_child().unwrap()
-            .as_text().unwrap();
+        const WHAT: &str = "#gdd row 7 (Favorited): expected \"N times\"";
+
+        let text = next_row("Favorited")?
+            .first_child()
+            .and_then(|node| node.as_text())
+            .context(WHAT)?;
 
         match text {
             "Never" => 0,
             "Once" => 1,
             "Twice" => 2,
             more => more // the text would be like "(n) times"
-                .strip_suffix(" times").unwrap()
-                .parse::<usize>()?
+                .strip_suffix(" times").context(WHAT)?
+                .parse::<usize>().context(WHAT)?
         }
     };
 
-    let rating_count = doc
-        .find(Attr("id", "rating_count"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
-        .as_text().unwrap()
-        .parse::<usize>()?;
-
-    let rating = doc
-        .find(Attr("id", "rating_label"))
-        .nth(0).unwrap()
-        .first_child().unwrap()
-        .as_text().unwrap()
-        .split_ascii_whitespace()
-        .nth(1).unwrap()
-        .parse::<f64>()?;
+    let rating_count = {
+        const WHAT: &str = "#rating_count: expected number";
+
+        doc.find(Attr("id", "rating_count"))
+            .next().context(WHAT)?
+            .first_child().context(WHAT)?
+            .as_text().context(WHAT)?
+            .parse::<usize>().context(WHAT)?
+    };
+
+    let rating = {
+        const WHAT: &str = "#rating_label: expected \"Average: N\"";
+
+        doc.find(Attr("id", "rating_label"))
+            .next().context(WHAT)?
+            .first_child().context(WHAT)?
+            .as_text().context(WHAT)?
+            .split_ascii_whitespace()
+            .nth(1).context(WHAT)?
+            .parse::<f64>().context(WHAT)?
+    };
 
     let tags = {
         let list = doc
             .find(Attr("id", "taglist"))
-            .nth(0).unwrap()
-            .first_child().unwrap()
-            .first_child().unwrap();
+            .next().context("#taglist: expected table")?
+            .first_child().context("#taglist: expected table")?
+            .first_child().context("#taglist: expected table body")?;
 
         let mut tags = TagMap::new();
 
-        for row in list.children() {
+        for (i, row) in list.children().enumerate() {
+            let what = format!("#taglist row {}: expected \"namespace:\"", i + 1);
+
             // remove last colon and parse
             let cat = row
-                .first_child().unwrap()
-                .first_child().unwrap()
-                .as_text().unwrap();
-            let cat = cat[..(cat.len() - 1)].parse::<TagKind>()?;
-
-            for elem in row.last_child().unwrap().children() {
+                .first_child()
+                .and_then(|node| node.first_child())
+                .and_then(|node| node.as_text())
+                .and_then(|text| text.strip_suffix(':'))
+                .context(&what)?
+                .parse::<TagKind>().context(&what)?;
+
+            let list = row
+                .last_child()
+                .context(&format!("#taglist row {}: expected tags", i + 1))?;
+
+            for elem in list.children() {
                 tags[cat].push(elem.text());
             }
         }
@@ -327,24 +505,53 @@ pub fn article(doc: &Document, path: String)
     })
 }
 
-pub fn comments(doc: &Document) -> Result<Vec<Comment>, Box<dyn Error>> {
+pub fn comments(doc: &Document) -> Result<Vec<Comment>, ParseError> {
     // parse comments; .c1 is a class each comment node belongs to
-    doc.find(Class("c1")).map(|node| comment(&node)).collect()
+    doc.find(Class("c1"))
+        .enumerate()
+        .map(|(i, node)| comment(&node)
+            .map_err(|err| ParseError::new(
+                format!("comment {}: {}", i + 1, err.context)
+            )))
+        .collect()
+}
+
+// parse integer which is always prefixed with a '+' or '-' sign
+// necessary for parsing score of a comment, which is prefixed so
+fn parse_prefixed(text: &str) -> Option<i64> {
+    // rust's parse() can't comprehend prefix '+' sign
+    let (sign, rest) = if let Some(rest) = text.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = text.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return None;
+    };
+
+    rest.parse::<i64>().ok().map(|x| x * sign)
+}
+
+// parse a string formatted like "(writer) (score)"
+fn parse_vote(vote: &str) -> Option<(String, i64)> {
+    // position of the last whitespace
+    let pos = vote.rfind(' ')?;
+
+    Some((vote[..pos].to_owned(), parse_prefixed(&vote[(pos + 1)..])?))
 }
 
-fn comment(node: &Node) -> Result<Comment, Box<dyn Error>> {
+fn comment(node: &Node) -> Result<Comment, ParseError> {
     let (top, bottom, votes, edited) = {
         let mut iter = node.children();
 
-        let first = iter.next().unwrap();
-        let second = iter.next().unwrap();
-        let third = iter.next().unwrap();
+        let first = iter.next().context(".c2: expected header")?;
+        let second = iter.next().context(".c6: expected content")?;
+        let third = iter.next();
         let fourth = iter.next();
 
-        // if the comment is edited, "Last edited on (date)." 
+        // if the comment is edited, "Last edited on (date)."
         // message is shown before the votes list
-        if let Some(fourth) = fourth {
-            (first, second, fourth, Some(third))
+        if fourth.is_some() {
+            (first, second, fourth, third)
         } else {
             (first, second, third, None)
         }
@@ -353,35 +560,27 @@ fn comment(node: &Node) -> Result<Comment, Box<dyn Error>> {
     let (left, right) = {
         let mut iter = top.children();
 
-        (iter.next().unwrap(), iter.next().unwrap())
+        (
+            iter.next().context(".c3: expected \"Posted on\" line")?,
+            iter.next().context(".c2: expected score")?
+        )
     };
 
-    // parse integer which is always prefixed with a '+' or '-' sign
-    // necessary for parsing score of a comment, which is prefixed so
-    fn parse_prefixed(text: &str) -> Result<i64, ParseIntError> {
-        // rust's parse() can't comprehend prefix '+' sign
-        let sign = match text.chars().nth(0) {
-            Some('+') => 1,
-            Some('-') => -1,
-            _ => unreachable!() // logically impossible
-        };
-    
-        text[1..].parse::<i64>().map(|x| x * sign)
-    }
-    
     let (posted, writer) = {
         let mut iter = left.children();
 
         let posted = iter
-            .next().unwrap()
-            .as_text().unwrap()
-            .strip_prefix("Posted on ").unwrap()
-            .strip_suffix(" by:   ").unwrap() // " by: &nbsp; "
+            .next()
+            .and_then(|node| node.as_text())
+            .and_then(|text| text.strip_prefix("Posted on "))
+            .and_then(|text| text.strip_suffix(" by: \u{a0} ")) // " by: &nbsp; "
+            .context(".c3: expected \"Posted on (date) by:\"")?
             .to_owned();
 
         let writer = iter
-            .next().unwrap()
-            .first_child().unwrap()
+            .next()
+            .and_then(|node| node.first_child())
+            .context(".c3: expected writer")?
             .text();
 
         (posted, writer)
@@ -390,47 +589,48 @@ fn comment(node: &Node) -> Result<Comment, Box<dyn Error>> {
     let vote = if right.is(Class("c4")) {
         None
     } else {
-        let text = right
-            .last_child().unwrap()
-            .first_child().unwrap()
-            .as_text().unwrap();
-
-        let score = parse_prefixed(text)?;
-
-        // parse a string formatted like "(writer) (score)"
-        fn parse_vote(vote: &str) -> Result<(String, i64), ParseIntError> {
-            // position of the last whitespace
-            let pos = vote.rfind(' ').unwrap();
-
-            Ok((vote[..pos].to_owned(), parse_prefixed(&vote[(pos + 1)..])?))
-        }
-
-        let omitted = votes
-            .last_child().unwrap()
-            .as_text()
+        let score = right
+            .last_child()
+            .and_then(|node| node.first_child())
+            .and_then(|node| node.as_text())
+            .and_then(parse_prefixed)
+            .context(".c5: expected score like \"+12\"")?;
+
+        let votes = votes.context(".c7: expected list of votes")?;
+
+        let omitted = match votes
+            .last_child()
+            .and_then(|node| node.as_text())
             .and_then(|text| text.strip_prefix(", and "))
-            .and_then(|text| text.strip_suffix(" more..."))
-            .map_or(Ok(0), |text| text.parse())?;
+            .and_then(|text| text.strip_suffix(" more...")) {
+            Some(text) => text
+                .parse::<usize>()
+                .context(".c7: expected \", and N more...\"")?,
+            None => 0,
+        };
 
         let voters = {
             let mut list = Vec::new();
 
             let base = votes
-                .first_child().unwrap()
-                .as_text().unwrap();
+                .first_child()
+                .and_then(|node| node.as_text())
+                .context(".c7: expected base score")?;
 
             let base = base
                 .strip_suffix(", ")
                 .unwrap_or(base);
 
-            list.push(parse_vote(base)?);
+            list.push(parse_vote(base).context(".c7: expected \"Base +N\"")?);
 
             for span in votes.find(Name("span")) {
                 let vote = span
-                    .first_child().unwrap()
-                    .as_text().unwrap();
+                    .first_child()
+                    .and_then(|node| node.as_text())
+                    .and_then(parse_vote)
+                    .context(".c7 span: expected \"(writer) +N\"")?;
 
-                list.push(parse_vote(vote)?);
+                list.push(vote);
             }
 
             list
@@ -443,19 +643,18 @@ fn comment(node: &Node) -> Result<Comment, Box<dyn Error>> {
         })
     };
 
-    let edited = if let Some(node) = edited {
-        Some(node
+    let edited = match edited {
+        Some(node) => Some(node
             .children()
-            .nth(1).unwrap()
-            .text())
-    } else {
-        None
+            .nth(1).context(".c8: expected \"Last edited on (date)\"")?
+            .text()),
+        None => None,
     };
 
     let content = bottom
-        .first_child().unwrap()
+        .first_child().context(".c6: expected content")?
         .text();
-    
+
     Ok(Comment {
         posted,
         edited,
@@ -470,38 +669,250 @@ fn comment(node: &Node) -> Result<Comment, Box<dyn Error>> {
 // NOTE: this function can only get 40 images in maximum at a time. get document
 // of another page and call this again to obtain all images.
 pub fn image_list(doc: &Document)
-    -> Result<Vec<String>, Box<dyn Error>> {
+    -> Result<Vec<String>, ParseError> {
     let mut images = Vec::new();
-    
+
     // is finding from id faster? i can just find by class as well...
-    let list = doc.find(Attr("id", "gdt")).nth(0).unwrap();
+    let list = doc
+        .find(Attr("id", "gdt"))
+        .next().context("#gdt: expected thumbnail list")?;
 
-    for node in list.children() {
+    for (i, node) in list.children().enumerate() {
         // advert!
         if node.attr("class") != Some("gdtm") {
             continue;
         }
 
         let link = node
-            .first_child().unwrap()
-            .first_child().unwrap()
-            .attr("href").unwrap()
+            .first_child()
+            .and_then(|node| node.first_child())
+            .and_then(|node| node.attr("href"))
+            .context(&format!("#gdt item {}: expected link to image page", i + 1))?
             .to_string();
 
         images.push(link);
     }
-    
+
     Ok(images)
 }
 
 // get the actual path to image
 pub fn image(doc: &Document)
-    -> Result<String, Box<dyn Error>> {
+    -> Result<String, ParseError> {
     Ok(
         doc
         .find(Attr("id", "img"))
-        .nth(0).unwrap()
-        .attr("src").unwrap()
+        .next()
+        .and_then(|node| node.attr("src"))
+        .context("#img: expected image src")?
         .to_string()
     )
 }
+
+// take a document of an image page, return the key for asking another
+// image server, found in "Reload broken image" like "return nl('1234-5678')"
+pub fn reload_key(doc: &Document) -> Option<String> {
+    let onclick = doc
+        .find(Attr("id", "loadfail"))
+        .next()?
+        .attr("onclick")?;
+
+    let begin = onclick.find("nl('")? + "nl('".len();
+    let end = begin + onclick[begin..].find('\'')?;
+
+    Some(onclick[begin..end].to_owned()).filter(|key| !key.is_empty())
+}
+
+// take a document of an image page, return the link to the original image;
+// it's there only if the image shown is resampled
+pub fn original(doc: &Document) -> Option<String> {
+    doc.find(Name("a"))
+        .filter_map(|node| node.attr("href"))
+        .find(|href| href.contains("/fullimg"))
+        .map(str::to_owned)
+}
+
+// take a document of an image page, return all it says about the image
+pub fn image_page(doc: &Document, path: String)
+    -> Result<ImagePage, ParseError> {
+    image_info(doc, path.clone()).map_err(|err| err.at(&path))
+}
+
+fn image_info(doc: &Document, path: String)
+    -> Result<ImagePage, ParseError> {
+    let src = image(doc)?;
+
+    // navigation has a counter like "<span>2</span> / <span>42</span>"
+    // between the links to the previous and the next page
+    let link = |id: &str| doc
+        .find(Attr("id", id))
+        .next()
+        .and_then(|node| node.attr("href"))
+        .map(str::to_owned)
+        .context(&format!("#{}: expected link", id));
+
+    let prev = link("prev")?;
+    let next = link("next")?;
+
+    let index = {
+        const WHAT: &str = "#i2 .sn: expected \"N / M\"";
+
+        doc.find(Attr("id", "i2").descendant(Class("sn")))
+            .next().context(WHAT)?
+            .find(Name("span"))
+            .next().context(WHAT)?
+            .text()
+            .parse::<usize>().context(WHAT)?
+            .checked_sub(1).context(WHAT)?
+    };
+
+    // e.g. "002.jpg :: 1280 x 1810 :: 412.5 KiB"
+    let (filename, width, height, file_size) = {
+        const WHAT: &str = "#i2: expected \"name :: W x H :: size\"";
+
+        let text = doc
+            .find(Attr("id", "i2"))
+            .next().context(WHAT)?
+            .children()
+            .map(|node| node.text())
+            .find(|text| text.contains(" :: ")).context(WHAT)?;
+
+        let mut parts = text.split(" :: ");
+        let filename = parts.next().context(WHAT)?.trim().to_owned();
+        let (width, height) = parts
+            .next()
+            .and_then(|dims| dims.split_once(" x "))
+            .context(WHAT)?;
+        let file_size = parts.next().context(WHAT)?.trim().to_owned();
+
+        (
+            filename,
+            width.trim().parse::<u32>().context(WHAT)?,
+            height.trim().parse::<u32>().context(WHAT)?,
+            file_size
+        )
+    };
+
+    Ok(ImagePage {
+        path,
+        index,
+        src,
+        filename,
+        width,
+        height,
+        file_size,
+        original: original(doc),
+        nl: reload_key(doc),
+        prev,
+        next,
+    })
+}
+
+// take the page the link to an original image answers with instead of
+// the image, return why we didn't get it; the site tells it in a notice
+// box, e.g. "You do not have enough GP to download this file."
+pub fn original_notice(html: &str) -> crate::Error {
+    let doc = Document::from(html);
+
+    let notice = doc
+        .find(Class("d").descendant(Name("p")))
+        .next()
+        .map(|node| node.text().trim().to_owned())
+        .unwrap_or_default();
+
+    if notice.starts_with("You do not have enough GP") {
+        crate::Error::GpRequired
+    } else if notice.starts_with("You must be logged in") {
+        crate::Error::Unauthorized
+    } else if notice.starts_with("You have exceeded your image viewing limits") {
+        crate::Error::QuotaExceeded
+    } else {
+        let end = notice.char_indices().nth(80).map_or(notice.len(), |(i, _)| i);
+        ParseError::new(format!("fullimg: unexpected notice {:?}", &notice[..end])).into()
+    }
+}
+
+// numbers on the account pages come with thousands separators
+fn parse_count(text: &str) -> Option<u64> {
+    text.trim().replace(',', "").parse::<u64>().ok()
+}
+
+// take any account page, return whether it's the notice shown to guests
+pub fn login_required(doc: &Document) -> bool {
+    doc.find(Class("stuffbox"))
+        .any(|node| node.text().contains("requires you to log on"))
+}
+
+// take the home page, return how much of the image limit is used
+pub fn image_limit(doc: &Document) -> Result<ImageLimit, ParseError> {
+    const WHAT: &str = ".homebox: expected \"You are currently at N towards a limit of M\"";
+
+    let paragraphs = || doc.find(Class("homebox").descendant(Name("p")));
+
+    let current = paragraphs()
+        .find(|node| node.text().contains("currently at"))
+        .context(WHAT)?;
+
+    let mut numbers = current.find(Name("strong")).map(|node| parse_count(&node.text()));
+    let used = numbers.next().flatten().context(WHAT)?;
+    let max = numbers.next().flatten().context(WHAT)?;
+
+    // only offered once something is used, e.g. "Reset Cost: 123 GP"
+    let reset_cost = paragraphs()
+        .find(|node| node.text().contains("Reset Cost"))
+        .and_then(|node| node.find(Name("strong")).next())
+        .and_then(|node| parse_count(&node.text()));
+
+    Ok(ImageLimit {
+        used,
+        max,
+        reset_cost,
+    })
+}
+
+// take the exchange page, return the balances of GP and credits;
+// they're shown like "Available: 12,345 kGP"
+pub fn balances(doc: &Document) -> Result<(u64, u64), ParseError> {
+    const WHAT: &str = "expected \"Available: N kGP\" and \"Available: N Credits\"";
+
+    let mut gp = None;
+    let mut credits = None;
+
+    for text in doc.find(Text).filter_map(|node| node.as_text()) {
+        let balance = match text.trim().strip_prefix("Available:") {
+            Some(balance) => balance.trim(),
+            None => continue,
+        };
+
+        if let Some(n) = balance.strip_suffix("kGP").and_then(parse_count) {
+            let n = n.checked_mul(1000).context("expected a balance of kGP that fits in u64")?;
+            gp = Some(n);
+        } else if let Some(n) = balance.strip_suffix("GP").and_then(parse_count) {
+            gp = Some(n);
+        } else if let Some(n) = balance.strip_suffix("Credits").and_then(parse_count) {
+            credits = Some(n);
+        }
+    }
+
+    Ok((gp.context(WHAT)?, credits.context(WHAT)?))
+}
+
+// take the hath perks page, return the names of the perks
+// the account has acquired; there may be none, but not the table
+pub fn hath_perks(doc: &Document) -> Result<Vec<String>, ParseError> {
+    const WHAT: &str = "expected a table of perks";
+
+    let table = doc
+        .find(Name("table"))
+        .find(|table| table.find(Name("th")).any(|cell| cell.text().trim() == "Perk"))
+        .context(WHAT)?;
+
+    let perks = table
+        .find(Name("tr"))
+        .filter(|row| row.find(Name("td")).any(|cell| cell.text().trim() == "Acquired"))
+        .filter_map(|row| row.find(Name("td")).next())
+        .map(|cell| cell.text().trim().to_owned())
+        .collect();
+
+    Ok(perks)
+}
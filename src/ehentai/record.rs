@@ -0,0 +1,182 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE};
+use tokio::fs;
+
+use crate::Error;
+use super::transport::{BoxFuture, Transport};
+
+// the same url written differently should find the same fixture:
+// lowercase scheme and host, no default port, sorted query, no fragment
+pub(super) fn normalize(uri: &Uri) -> String {
+    let scheme = uri.scheme_str().unwrap_or("https").to_ascii_lowercase();
+    let host = uri.host().unwrap_or_default().to_ascii_lowercase();
+
+    let port = match (uri.port_u16(), scheme.as_str()) {
+        (Some(80), "http") | (Some(443), "https") | (None, _) => String::new(),
+        (Some(port), _) => format!(":{}", port),
+    };
+
+    let path = match uri.path() {
+        "" => "/",
+        path => path,
+    };
+
+    let mut query = uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .collect::<Vec<_>>();
+    query.sort_unstable();
+
+    let query = if query.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", query.join("&"))
+    };
+
+    format!("{}://{}{}{}{}", scheme, host, port, path, query)
+}
+
+// 64-bit FNV-1a; stable across runs and platforms, unlike DefaultHasher
+pub(super) fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// where the response to a request is kept: "<hash>.head" has the request
+// and the status line with headers, "<hash>.body" has the body as it came
+fn fixture(dir: &Path, method: &Method, uri: &Uri) -> (PathBuf, PathBuf) {
+    let key = format!("{} {}", method, normalize(uri));
+    let name = format!("{:016x}", fnv1a(&key));
+
+    (dir.join(format!("{}.head", name)), dir.join(format!("{}.body", name)))
+}
+
+// fixtures are meant to be shared, so anything that would give
+// the account (or the proxy) away is left out of them
+fn sensitive(name: &HeaderName) -> bool {
+    [COOKIE, SET_COOKIE, AUTHORIZATION, PROXY_AUTHORIZATION].contains(name)
+}
+
+fn write_headers(text: &mut String, headers: &HeaderMap) {
+    for (name, value) in headers.iter().filter(|(name, _)| !sensitive(name)) {
+        text.push_str(&format!("{}: {}\n", name, String::from_utf8_lossy(value.as_bytes())));
+    }
+}
+
+fn invalid(path: &Path, what: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), what)
+    ))
+}
+
+// passes requests on to another transport,
+// saving what comes back into a fixture directory
+pub struct RecordTransport {
+    inner: Arc<dyn Transport>,
+    dir: PathBuf,
+}
+
+impl RecordTransport {
+    pub fn new(inner: Arc<dyn Transport>, dir: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    async fn record(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        let (head, body) = fixture(&self.dir, req.method(), req.uri());
+
+        let mut text = format!("{} {}\n", req.method(), req.uri());
+        write_headers(&mut text, req.headers());
+
+        let res = self.inner.send(req).await?;
+        let (parts, data) = res.into_parts();
+        let data = hyper::body::to_bytes(data).await?;
+
+        text.push_str(&format!("\n{}\n", parts.status.as_u16()));
+        write_headers(&mut text, &parts.headers);
+
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(&head, text).await?;
+        fs::write(&body, &data).await?;
+
+        Ok(Response::from_parts(parts, Body::from(data)))
+    }
+}
+
+impl Transport for RecordTransport {
+    fn send(&self, req: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(self.record(req))
+    }
+}
+
+// answers with what RecordTransport has saved, never touching the network
+pub struct ReplayTransport {
+    dir: PathBuf,
+}
+
+impl ReplayTransport {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    async fn replay(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        let (head, body) = fixture(&self.dir, req.method(), req.uri());
+
+        let text = match fs::read_to_string(&head).await {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no recorded response for {} {}", req.method(), req.uri())
+                )));
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        // skip the request; the response starts after the blank line
+        let (_, response) = text.split_once("\n\n").ok_or_else(|| invalid(&head, "no response"))?;
+        let mut lines = response.lines();
+
+        let status = lines
+            .next()
+            .and_then(|line| line.trim().parse::<u16>().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .ok_or_else(|| invalid(&head, "malformed status"))?;
+
+        let mut res = Response::new(Body::from(fs::read(&body).await?));
+        *res.status_mut() = status;
+
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(": ").ok_or_else(|| invalid(&head, line))?;
+
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid(&head, line))?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid(&head, line))?;
+
+            res.headers_mut().append(name, value);
+        }
+
+        Ok(res)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, req: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(self.replay(req))
+    }
+}
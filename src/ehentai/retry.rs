@@ -0,0 +1,118 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::future::Future;
+use std::time::Duration;
+use rand::Rng;
+use tokio::time::sleep;
+
+use crate::Error;
+
+// how persistently a request is retried when it fails
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retryable: fn(&Error) -> bool,
+}
+
+impl RetryPolicy {
+    // 3 attempts in total, waiting 0.5s, 1s, ... in between
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            retryable: Error::is_transient,
+        }
+    }
+
+    // try only once
+    pub fn never() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    // number of attempts including the first one
+    pub fn max_attempts(mut self, n: u32) -> Self {
+        self.max_attempts = n.max(1);
+        self
+    }
+
+    // wait `base` after the first failure, doubling each time up to `max`;
+    // a server asking to wait longer than `max` (with Retry-After)
+    // makes us give up instead
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.base_delay = base;
+        self.max_delay = max.max(base);
+        self
+    }
+
+    // randomize each delay between its half and itself, so that
+    // many failed downloads don't come back at the same moment
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    // which errors are worth another try; Error::is_transient by default
+    pub fn retry_if(mut self, retryable: fn(&Error) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    // how long to wait after `attempt`th (starting from 1) attempt failed,
+    // or None if we should give up
+    pub(super) fn delay(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(self.retryable)(err) {
+            return None;
+        }
+
+        if let Error::RateLimited(Some(after)) = err {
+            return Some(*after).filter(|after| after <= &self.max_delay);
+        }
+
+        let exp = 2u32.saturating_pow(attempt - 1);
+        let delay = self.base_delay
+            .checked_mul(exp)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+
+        if self.jitter && delay > Duration::from_millis(1) {
+            let half = delay / 2;
+            Some(half + rand::thread_rng().gen_range(Duration::ZERO..=half))
+        } else {
+            Some(delay)
+        }
+    }
+
+    pub(super) async fn run<T, F, Fut>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let err = match f().await {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+
+            match self.delay(attempt, &err) {
+                Some(delay) => sleep(delay).await,
+                None => return Err(err),
+            }
+
+            attempt += 1;
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,159 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::pin::Pin;
+use std::future::Future;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::stream::{Stream, StreamExt};
+use hyper::{Body, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_RANGE, ETAG};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::Error;
+use super::integrity::Checksum;
+
+// body of an image response, yielded chunk by chunk as it arrives
+pub struct ImageStream {
+    body: Body,
+    length: Option<u64>,
+    // (first byte, total length) if only a part of the image is being sent
+    pub(super) range: Option<(u64, Option<u64>)>,
+    pub(super) etag: Option<String>,
+    read_timeout: Option<Duration>,
+    timer: Option<Pin<Box<Sleep>>>,
+    // checks the image once it's all there, if we know its hash
+    checksum: Option<Checksum>,
+    // keeps our slot in the client's concurrency limit until dropped
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ImageStream {
+    pub(super) fn new(
+        res: Response<Body>,
+        permit: Option<OwnedSemaphorePermit>,
+        read_timeout: Option<Duration>
+    ) -> Self {
+        let range = if res.status() == StatusCode::PARTIAL_CONTENT {
+            res.headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(content_range)
+        } else {
+            None
+        };
+
+        let etag = res.headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let body = res.into_body();
+        let length = body.size_hint().exact();
+
+        Self {
+            body,
+            length,
+            range,
+            etag,
+            read_timeout,
+            timer: read_timeout.map(|timeout| Box::pin(sleep(timeout))),
+            checksum: None,
+            _permit: permit,
+        }
+    }
+
+    // fail at the end if the image doesn't match `expected`, the beginning
+    // of its SHA-1; makes sense only if the whole image is being sent
+    pub(super) fn verify(mut self, expected: &str) -> Self {
+        self.checksum = Some(Checksum::new(expected));
+        self
+    }
+
+    // size of the whole image in bytes, if the server told us;
+    // for a partial response that's the total in Content-Range,
+    // not how much of it is being sent
+    pub fn content_length(&self) -> Option<u64> {
+        match self.range {
+            Some((_, total)) => total,
+            None => self.length,
+        }
+    }
+
+    // write the rest of the image into `writer`,
+    // returning how many bytes were written
+    pub async fn write_to<W>(mut self, mut writer: W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut written = 0;
+
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        writer.flush().await?;
+        Ok(written)
+    }
+}
+
+impl Stream for ImageStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        match Pin::new(&mut this.body).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                // the server is alive; wait for the next chunk from now on
+                if let (Some(timer), Some(timeout)) = (&mut this.timer, this.read_timeout) {
+                    timer.as_mut().reset(Instant::now() + timeout);
+                }
+
+                if let Some(checksum) = &mut this.checksum {
+                    checksum.update(&chunk);
+                }
+
+                Poll::Ready(Some(Ok(chunk)))
+            },
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => match this.checksum.take().map(Checksum::finish) {
+                Some(Err(err)) => Poll::Ready(Some(Err(err))),
+                _ => Poll::Ready(None),
+            },
+            Poll::Pending => {
+                let stalled = this.timer
+                    .as_mut()
+                    .map_or(false, |timer| timer.as_mut().poll(cx).is_ready());
+
+                if stalled {
+                    Poll::Ready(Some(Err(Error::Timeout)))
+                } else {
+                    Poll::Pending
+                }
+            },
+        }
+    }
+}
+
+// parse "bytes 400-999/1000" (or "bytes 400-999/*") into (400, Some(1000))
+pub(super) fn content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+
+    Some((start.parse().ok()?, total))
+}
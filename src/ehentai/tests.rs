@@ -1,8 +1,23 @@
 use super::*;
+use super::{parser, client};
+
+// the live tests talk to the site, unless LUDE_REPLAY points to
+// the fixtures recorded before with LUDE_RECORD
+fn live_explorer() -> Explorer {
+    let builder = Explorer::builder();
+
+    let builder = match (std::env::var_os("LUDE_REPLAY"), std::env::var_os("LUDE_RECORD")) {
+        (Some(dir), _) => builder.replay(dir),
+        (None, Some(dir)) => builder.record(dir),
+        _ => builder,
+    };
+
+    builder.build().unwrap()
+}
 
 #[tokio::test]
 async fn search() {
-    let explorer = Explorer::new();
+    let explorer = live_explorer();
     let mut page = explorer.search("language:korean").take(3);
 
     while let Some(list) = page.next().await.unwrap() {
@@ -21,7 +36,7 @@ async fn light() {
     use std::fs::File;
     use std::io::Write;
 
-    let explorer = Explorer::new();
+    let explorer = live_explorer();
     let article = explorer.article_from_path(URL.into()).await.unwrap();
     
     let thumb = article.load_thumb().await.unwrap();
@@ -36,7 +51,7 @@ async fn sequential() {
     use std::io::Write;
     use std::path::PathBuf;
 
-    let explorer = Explorer::new();
+    let explorer = live_explorer();
     let mut article = explorer.article_from_path(URL.into()).await.unwrap();
     article.load_image_list().await.unwrap();
 
@@ -67,7 +82,6 @@ async fn sequential() {
 // FIXME
 #[tokio::test]
 async fn parallel() {
-    use tokio::time::{sleep, Duration};
     use tokio::sync::mpsc;
     use std::sync::Arc;
     use std::fs;
@@ -76,7 +90,7 @@ async fn parallel() {
     use std::path::PathBuf;
 
     // load article infos 
-    let explorer = Explorer::new();
+    let explorer = live_explorer();
 
     let mut article = explorer.article_from_path(URL.into()).await.unwrap();
     article.load_image_list().await.unwrap();
@@ -87,9 +101,8 @@ async fn parallel() {
 
     let article = Arc::new(article);
 
+    // no need to sleep between spawns; the client throttles requests by itself
     for i in 0..len {
-        sleep(Duration::from_millis(100)).await;
-
         let tx = tx.clone();
         let article = Arc::clone(&article);
 
@@ -123,3 +136,1942 @@ async fn parallel() {
         file.write_all(&image).unwrap();
     }
 }
+
+#[test]
+fn error_is_thread_safe() {
+    fn assert_bounds<T: Send + Sync + 'static>() {}
+    assert_bounds::<crate::Error>();
+}
+
+// the gallery, search, image, home and exchange pages come whole, with the
+// navigation, scripts and forms around what's parsed, and no keys or account
+// details; ExplorerBuilder::record() saves fresh bodies to compare them with
+const GALLERY: &str = include_str!("fixtures/gallery.html");
+const GALLERY_PARENT: &str = include_str!("fixtures/gallery_parent.html");
+const SEARCH: &str = include_str!("fixtures/search.html");
+const SEARCH_EMPTY: &str = include_str!("fixtures/search_empty.html");
+const SEARCH_INVALID: &str = include_str!("fixtures/search_invalid.html");
+const IMAGE: &str = include_str!("fixtures/image.html");
+const HOME: &str = include_str!("fixtures/home.html");
+const HOME_GUEST: &str = include_str!("fixtures/home_guest.html");
+const EXCHANGE: &str = include_str!("fixtures/exchange.html");
+const HATH_PERKS: &str = include_str!("fixtures/hathperks.html");
+const NOTICE_GP: &str = include_str!("fixtures/notice_gp.html");
+const NOTICE_LOGIN: &str = include_str!("fixtures/notice_login.html");
+const NOTICE_LIMIT: &str = include_str!("fixtures/notice_limit.html");
+
+// run every parser on the document; we only care that none of them panics
+fn parse_everything(html: &str) {
+    use select::document::Document;
+
+    let doc = Document::from(html);
+    let _ = parser::search_results(&doc);
+    let _ = parser::article_list(&doc);
+    let _ = parser::article(&doc, "/g/1088955/4464b39d07/".into());
+    let _ = parser::comments(&doc);
+    let _ = parser::image_list(&doc);
+    let _ = parser::image(&doc);
+    let _ = parser::image_page(&doc, "/s/5f6a7b8c9d/1088955-2".into());
+    let _ = parser::unavailable(&doc);
+    let _ = parser::login_required(&doc);
+    let _ = parser::image_limit(&doc);
+    let _ = parser::balances(&doc);
+    let _ = parser::hath_perks(&doc);
+    let _ = parser::original_notice(html);
+}
+
+const FIXTURES: &[&str] = &[
+    GALLERY, GALLERY_PARENT, SEARCH, SEARCH_EMPTY, SEARCH_INVALID, IMAGE,
+    HOME, HOME_GUEST, EXCHANGE, HATH_PERKS, NOTICE_GP, NOTICE_LOGIN, NOTICE_LIMIT,
+];
+
+#[test]
+fn parse_gallery() {
+    use select::document::Document;
+
+    let doc = Document::from(GALLERY);
+    let meta = parser::article(&doc, URL.into()).unwrap();
+
+    assert_eq!(meta.path, URL);
+    assert_eq!(meta.title, "[Sample Circle] Sample Gallery (Original) [Korean]");
+    assert_eq!(meta.original_title, "[サンプル] サンプル [韓国翻訳]");
+    assert!(matches!(meta.kind, ArticleKind::Doujinshi));
+    assert_eq!(
+        meta.thumb,
+        "https://ehgt.org/5f/6e/5f6e0b7d1c2a3e4f5a6b7c8d9e0f1a2b3c4d5e6f-1234567-1280-1810-jpg_250.jpg"
+    );
+    assert_eq!(meta.uploader, "sampler");
+    assert_eq!(meta.posted, "2017-06-21 12:16");
+    assert_eq!(meta.parent, None);
+    assert!(meta.visible);
+    assert_eq!(meta.language, "Korean");
+    assert!(meta.translated);
+    assert_eq!(meta.file_size, "33.42 MB");
+    assert_eq!(meta.length, 42);
+    assert_eq!(meta.favorited, 561);
+    assert_eq!(meta.rating_count, 123);
+    assert_eq!(meta.rating, 4.62);
+    assert_eq!(meta.tags[TagKind::Language], ["korean", "translated"]);
+    assert_eq!(meta.tags[TagKind::Parody], ["original"]);
+    assert_eq!(meta.tags[TagKind::Female], ["glasses", "ponytail"]);
+    assert!(meta.tags[TagKind::Artist].is_empty());
+
+    assert_eq!(parser::image_list(&doc).unwrap(), [
+        "https://e-hentai.org/s/7037807198/1088955-1",
+        "https://e-hentai.org/s/5f6a7b8c9d/1088955-2",
+        "https://e-hentai.org/s/0e1f2a3b4c/1088955-3",
+    ]);
+}
+
+#[test]
+fn parse_comments() {
+    use select::document::Document;
+
+    let comments = parser::comments(&Document::from(GALLERY)).unwrap();
+    assert_eq!(comments.len(), 3);
+
+    // the uploader's comment has no score
+    let uploader = &comments[0];
+    assert!(uploader.is_uploader());
+    assert_eq!(uploader.posted(), "21 June 2017, 12:20");
+    assert_eq!(uploader.writer(), "sampler");
+    assert_eq!(uploader.content(), "Thanks for reading!");
+    assert_eq!(uploader.edited(), None);
+    assert_eq!(uploader.score(), None);
+    assert!(uploader.voters().is_none());
+
+    let plain = &comments[1];
+    assert!(!plain.is_uploader());
+    assert_eq!(plain.writer(), "alice");
+    assert_eq!(plain.content(), "Great translation.");
+    assert_eq!(plain.edited(), None);
+    assert_eq!(plain.score(), Some(12));
+    assert_eq!(plain.voters().unwrap().cloned().collect::<Vec<_>>(), [
+        (String::from("Base"), 6),
+        (String::from("bob"), 3),
+        (String::from("carol"), 3),
+    ]);
+    assert_eq!(plain.omitted_voter(), Some(0));
+
+    // edited, with some of the voters left out
+    let edited = &comments[2];
+    assert_eq!(edited.posted(), "23 June 2017, 19:45");
+    assert_eq!(edited.writer(), "dave");
+    assert_eq!(edited.content(), "Page 3 is missing a line.");
+    assert_eq!(edited.edited(), Some("23 June 2017, 20:02"));
+    assert_eq!(edited.score(), Some(-5));
+    assert_eq!(edited.voters().unwrap().cloned().collect::<Vec<_>>(), [
+        (String::from("Base"), 3),
+        (String::from("erin"), -4),
+        (String::from("frank"), -2),
+    ]);
+    assert_eq!(edited.omitted_voter(), Some(2));
+}
+
+#[test]
+fn parse_gallery_with_parent() {
+    use select::document::Document;
+
+    let path = "https://e-hentai.org/g/1500000/abcdef0123/";
+    let doc = Document::from(GALLERY_PARENT);
+    let meta = parser::article(&doc, path.into()).unwrap();
+
+    assert_eq!(meta.title, "[Tester] Sample Manga Ch. 2");
+    assert!(matches!(meta.kind, ArticleKind::Manga));
+    assert_eq!(meta.parent.as_deref(), Some("https://e-hentai.org/g/1000000/0123456789/"));
+    assert!(!meta.visible);
+    assert_eq!(meta.language, "Japanese");
+    assert!(!meta.translated);
+    assert_eq!(meta.length, 3);
+    assert_eq!(meta.favorited, 1);
+    assert_eq!(meta.rating_count, 7);
+    assert_eq!(meta.rating, 3.5);
+    assert_eq!(meta.tags[TagKind::Artist], ["tester"]);
+
+    // the ad among the thumbnails isn't an image
+    assert_eq!(parser::image_list(&doc).unwrap(), [
+        "https://e-hentai.org/s/1111111111/1500000-1",
+        "https://e-hentai.org/s/2222222222/1500000-2",
+        "https://e-hentai.org/s/3333333333/1500000-3",
+    ]);
+    assert!(parser::comments(&doc).unwrap().is_empty());
+}
+
+#[test]
+fn parse_search() {
+    use select::document::Document;
+
+    let doc = Document::from(SEARCH);
+    assert_eq!(parser::search_results(&doc).unwrap(), 1234);
+
+    // the ad row between them is skipped
+    let list = parser::article_list(&doc).unwrap().unwrap();
+    assert_eq!(list.len(), 2);
+
+    let first = &list[0];
+    assert!(matches!(first.kind, ArticleKind::Doujinshi));
+    assert_eq!(
+        first.thumb,
+        "https://ehgt.org/t/5f/6e/5f6e0b7d1c2a3e4f5a6b7c8d9e0f1a2b3c4d5e6f-1234567-1280-1810-jpg_250.jpg"
+    );
+    assert_eq!(first.posted, "2017-06-21 12:16");
+    assert_eq!(first.path, URL);
+    assert_eq!(first.title, "[Sample Circle] Sample Gallery (Original) [Korean]");
+    assert_eq!(first.tags[TagKind::Language], ["korean", "translated"]);
+    assert_eq!(first.tags[TagKind::Female], ["glasses"]);
+    assert_eq!(first.uploader, "sampler");
+    assert_eq!(first.length, 42);
+
+    let second = &list[1];
+    assert!(matches!(second.kind, ArticleKind::Manga));
+    assert_eq!(second.path, "https://e-hentai.org/g/1500000/abcdef0123/");
+    assert_eq!(second.tags[TagKind::Artist], ["tester"]);
+    assert_eq!(second.uploader, "tester");
+    assert_eq!(second.length, 3);
+}
+
+#[test]
+fn parse_search_empty() {
+    use select::document::Document;
+
+    let doc = Document::from(SEARCH_EMPTY);
+    assert_eq!(parser::search_results(&doc).unwrap(), 0);
+    assert!(parser::article_list(&doc).unwrap().is_none());
+
+    // a page past the last one
+    let doc = Document::from(SEARCH_INVALID);
+    assert_eq!(parser::search_results(&doc).unwrap(), 1234);
+    assert!(parser::article_list(&doc).unwrap().is_none());
+
+    // no counter and no notice either; the layout must have changed
+    assert!(parser::search_results(&Document::from(GALLERY)).is_err());
+}
+
+#[test]
+fn parse_image() {
+    use select::document::Document;
+
+    assert_eq!(
+        parser::image(&Document::from(IMAGE)).unwrap(),
+        "https://abcd1234.hath.network:8443/h/5f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6-422400-1280-1810-jpg/\
+        keystamp=1600000000-abcdef0123;fileindex=12345678;xres=1280/002.jpg"
+    );
+    assert!(parser::image(&Document::from(GALLERY)).is_err());
+}
+
+#[test]
+fn parse_image_page() {
+    use select::document::Document;
+
+    let path = "https://e-hentai.org/s/5f6a7b8c9d/1088955-2";
+    let page = parser::image_page(&Document::from(IMAGE), path.into()).unwrap();
+
+    assert_eq!(page.path, path);
+    assert_eq!(page.index, 1);
+    assert!(page.src.ends_with("/002.jpg"));
+    assert_eq!(page.filename, "002.jpg");
+    assert_eq!((page.width, page.height), (1280, 1810));
+    assert_eq!(page.file_size, "412.5 KiB");
+    assert_eq!(
+        page.original.as_deref(),
+        Some("https://e-hentai.org/fullimg.php?gid=1088955&page=2&key=0123456789")
+    );
+    assert_eq!(page.nl.as_deref(), Some("12345-67890"));
+    assert_eq!(page.prev, "https://e-hentai.org/s/7037807198/1088955-1");
+    assert_eq!(page.next, "https://e-hentai.org/s/0e1f2a3b4c/1088955-3");
+
+    // images small enough aren't resampled, so there's no original to ask for
+    let html = IMAGE.replace("fullimg.php", "g/blank.gif");
+    let page = parser::image_page(&Document::from(html.as_str()), path.into()).unwrap();
+    assert_eq!(page.original, None);
+
+    let html = IMAGE.replace(" x 1810", "x1810");
+    let err = parser::image_page(&Document::from(html.as_str()), path.into()).unwrap_err();
+    assert_eq!(err.path(), Some(path));
+    assert!(err.context().starts_with("#i2: expected"));
+}
+
+#[test]
+fn parse_account() {
+    use select::document::Document;
+
+    let limit = parser::image_limit(&Document::from(HOME)).unwrap();
+    assert_eq!((limit.used, limit.max, limit.reset_cost), (1234, 5000, Some(123)));
+    assert_eq!(limit.remaining(), 3766);
+    assert!(!parser::login_required(&Document::from(HOME)));
+
+    // nothing to reset yet
+    let html = HOME.replace("Reset Cost", "Reset");
+    let limit = parser::image_limit(&Document::from(html.as_str())).unwrap();
+    assert_eq!(limit.reset_cost, None);
+
+    let doc = Document::from(HOME_GUEST);
+    assert!(parser::image_limit(&doc).is_err());
+    assert!(parser::login_required(&doc));
+
+    let balances = parser::balances(&Document::from(EXCHANGE)).unwrap();
+    assert_eq!(balances, (12_345_000, 1_234_567));
+
+    let perks = parser::hath_perks(&Document::from(HATH_PERKS)).unwrap();
+    assert_eq!(perks, ["Bronze Star", "Silver Star"]);
+
+    // a changed layout isn't taken for an empty account
+    let html = EXCHANGE.replace("Credits", "C");
+    assert!(parser::balances(&Document::from(html.as_str())).is_err());
+
+    // nor does a balance too large to count panic
+    let html = EXCHANGE.replace("12,345 kGP", "18,446,744,073,709,552 kGP");
+    assert!(parser::balances(&Document::from(html.as_str())).is_err());
+
+    let html = HATH_PERKS.replace("<th>Perk</th>", "<th>Name</th>");
+    assert!(parser::hath_perks(&Document::from(html.as_str())).is_err());
+
+    let html = HATH_PERKS.replace("Acquired", "Purchase");
+    assert!(parser::hath_perks(&Document::from(html.as_str())).unwrap().is_empty());
+}
+
+#[test]
+fn parse_error_context() {
+    use select::document::Document;
+
+    let doc = Document::from(GALLERY.replace("42 pages", "42 sheets").as_str());
+    let err = parser::article(&doc, URL.into()).unwrap_err();
+
+    assert_eq!(err.path(), Some(URL));
+    assert!(err.context().starts_with("#gdd row 6 (Length): expected \"N pages\""));
+}
+
+#[test]
+fn parse_truncated() {
+    // cutting a word or a number short tells nothing new, but every cut
+    // next to markup (or anything else) does
+    let word = |byte: u8| byte.is_ascii_alphanumeric();
+
+    for html in FIXTURES {
+        let bytes = html.as_bytes();
+        let ends = (0..html.len())
+            .filter(|&i| html.is_char_boundary(i))
+            .filter(|&i| i == 0 || !word(bytes[i - 1]) || !word(bytes[i]));
+
+        for end in ends {
+            parse_everything(&html[..end]);
+        }
+    }
+}
+
+#[test]
+fn parse_mutated() {
+    for html in FIXTURES {
+        // drop each tag (with the text right after it) one at a time
+        let tags = html.match_indices('<').map(|(i, _)| i).collect::<Vec<_>>();
+
+        for pair in tags.windows(2) {
+            let html = format!("{}{}", &html[..pair[0]], &html[pair[1]..]);
+            parse_everything(&html);
+        }
+
+        // and mess up every number on the page
+        parse_everything(&html.replace(|c: char| c.is_ascii_digit(), "x"));
+        parse_everything(&html.replace('+', "").replace(" by:", ""));
+    }
+}
+
+#[test]
+fn ban_notice() {
+    use std::time::Duration;
+
+    let notice = "Your IP address has been temporarily banned for excessive \
+        pageloads which indicates that you are using automated \
+        mirroring/harvesting software. The ban expires in 1 day, \
+        2 hours and 30 minutes";
+
+    assert_eq!(
+        client::ban_expiry(notice),
+        Some(Duration::from_secs(24 * 3600 + 2 * 3600 + 30 * 60))
+    );
+    assert_eq!(client::ban_expiry("The ban expires in 59 seconds"), Some(Duration::from_secs(59)));
+    assert_eq!(client::ban_expiry("Your IP address has been temporarily banned"), None);
+    assert_eq!(client::ban_expiry("The ban expires in 99999999999999999 days"), None);
+    assert_eq!(client::ban_expiry("The ban expires in 18446744073709551615 seconds and 1 second"), None);
+}
+
+#[test]
+fn removed_gallery() {
+    use select::document::Document;
+
+    let doc = Document::from(
+        "<html><head><title>Gallery Not Available - E-Hentai Galleries</title></head>\
+        <body><div class=\"d\"><p>This gallery has been removed or is unavailable.</p></div></body></html>"
+    );
+
+    assert_eq!(
+        parser::unavailable(&doc).as_deref(),
+        Some("This gallery has been removed or is unavailable.")
+    );
+    assert_eq!(parser::unavailable(&Document::from(GALLERY)), None);
+}
+
+#[test]
+fn retry_delay() {
+    use std::time::Duration;
+    use hyper::StatusCode;
+    use crate::Error;
+
+    let policy = RetryPolicy::new()
+        .max_attempts(4)
+        .backoff(Duration::from_millis(100), Duration::from_millis(300))
+        .jitter(false);
+
+    let err = Error::Status(StatusCode::BAD_GATEWAY);
+    assert_eq!(policy.delay(1, &err), Some(Duration::from_millis(100)));
+    assert_eq!(policy.delay(2, &err), Some(Duration::from_millis(200)));
+    assert_eq!(policy.delay(3, &err), Some(Duration::from_millis(300)));
+    assert_eq!(policy.delay(4, &err), None);
+
+    // not worth retrying
+    assert_eq!(policy.delay(1, &Error::NotFound), None);
+    assert_eq!(policy.delay(1, &Error::Banned(None)), None);
+
+    // honor Retry-After unless it's too long
+    let err = Error::RateLimited(Some(Duration::from_millis(250)));
+    assert_eq!(policy.delay(1, &err), Some(Duration::from_millis(250)));
+    let err = Error::RateLimited(Some(Duration::from_secs(3600)));
+    assert_eq!(policy.delay(1, &err), None);
+}
+
+#[tokio::test]
+async fn retry_run() {
+    use std::time::Duration;
+    use std::cell::Cell;
+    use crate::Error;
+
+    let policy = RetryPolicy::new()
+        .max_attempts(3)
+        .backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+    let count = Cell::new(0);
+    let res = policy.run(|| {
+        count.set(count.get() + 1);
+        async { Err::<(), _>(Error::RateLimited(None)) }
+    }).await;
+
+    assert!(matches!(res, Err(Error::RateLimited(None))));
+    assert_eq!(count.get(), 3);
+
+    count.set(0);
+    let res = policy.run(|| {
+        count.set(count.get() + 1);
+        let n = count.get();
+        async move { if n < 2 { Err(Error::RateLimited(None)) } else { Ok(n) } }
+    }).await;
+
+    assert_eq!(res.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    use std::time::Duration;
+    use tokio::time::Instant;
+    use super::limit::Limiter;
+
+    let limiter = Limiter::new(RateLimit::new(20.0, 1), RateLimit::unlimited(), 4);
+    let start = Instant::now();
+
+    // a burst of 1 is free, the other two have to wait 50ms each
+    for _ in 0..3 {
+        let _permit = limiter.acquire("e-hentai.org").await;
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(90));
+
+    // image servers aren't throttled here, and each host has its own bucket
+    let start = Instant::now();
+
+    for _ in 0..10 {
+        let _permit = limiter.acquire("ehgt.org").await;
+    }
+
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    // hosts seen once are forgotten when they're idle
+    let limiter = Limiter::new(RateLimit::new(20.0, 1), RateLimit::new(1000.0, 1), 4);
+
+    for i in 0..200 {
+        let _permit = limiter.acquire(&format!("{}.hath.network", i)).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert!(limiter.hosts() < 100);
+}
+
+#[tokio::test]
+async fn builder() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use hyper::header::USER_AGENT;
+    use crate::Error;
+
+    let explorer = Explorer::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .read_timeout(Duration::from_secs(10))
+        .user_agent("lude-test")
+        .header("Accept-Language", "en-US")
+        .http_version(HttpVersion::Http2)
+        .detour(false)
+        .build();
+
+    assert!(explorer.is_ok());
+
+    // what's set reaches the requests
+    let memory = Arc::new(MemoryTransport::new().html(URL, GALLERY));
+
+    let explorer = Explorer::builder()
+        .transport(memory.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .user_agent("lude-test")
+        .header("Accept-Language", "en-US")
+        .header("X-Extra", "1")
+        .build()
+        .unwrap();
+
+    explorer.article_from_path(String::from(URL)).await.unwrap();
+
+    let requests = memory.requests();
+    let headers = requests[0].headers();
+    assert_eq!(headers[USER_AGENT], "lude-test");
+    assert_eq!(headers["Accept-Language"], "en-US");
+    assert_eq!(headers["X-Extra"], "1");
+
+    // and so do the timeouts
+    for (read, total) in [(Some(20), None), (None, Some(20))] {
+        let slow = Slow {
+            inner: memory.clone(),
+            delay: Duration::from_millis(200),
+        };
+
+        let mut builder = Explorer::builder()
+            .transport(Arc::new(slow))
+            .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+            .retry(RetryPolicy::never());
+
+        if let Some(ms) = read {
+            builder = builder.read_timeout(Duration::from_millis(ms));
+        }
+
+        if let Some(ms) = total {
+            builder = builder.total_timeout(Duration::from_millis(ms));
+        }
+
+        let res = builder.build().unwrap().article_from_path(String::from(URL)).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+    }
+
+    let explorer = Explorer::builder()
+        .header("Bad Header", "value")
+        .build();
+
+    assert!(matches!(explorer, Err(Error::InvalidInput(_))));
+
+    // detour only speaks HTTP/1
+    let explorer = Explorer::builder()
+        .http_version(HttpVersion::Http2)
+        .build();
+
+    assert!(matches!(explorer, Err(Error::InvalidInput(_))));
+}
+
+#[test]
+fn decode_body() {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder, DeflateEncoder};
+
+    let html = GALLERY.as_bytes().to_vec();
+
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(&html).unwrap();
+    assert_eq!(client::decode(Some("gzip"), gz.finish().unwrap()).unwrap(), html);
+
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&html).unwrap();
+    assert_eq!(client::decode(Some("deflate"), zlib.finish().unwrap()).unwrap(), html);
+
+    let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+    raw.write_all(&html).unwrap();
+    assert_eq!(client::decode(Some("deflate"), raw.finish().unwrap()).unwrap(), html);
+
+    assert_eq!(client::decode(None, html.clone()).unwrap(), html);
+    assert!(client::decode(Some("gzip"), html.clone()).is_err());
+    assert!(client::decode(Some("compress"), html).is_err());
+}
+
+#[test]
+fn decode_charset() {
+    use super::charset::decode_html;
+
+    // declared in the header
+    let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("<p>サンプル</p>");
+    let file = decode_html(&bytes, Some("text/html; charset=Shift_JIS"));
+    assert_eq!(file.text, "<p>サンプル</p>");
+    assert!(!file.lossy);
+
+    // declared in the document
+    let (body, _, _) = encoding_rs::EUC_KR.encode("<p>샘플</p>");
+    let mut bytes = b"<html><head><meta http-equiv=\"Content-Type\" \
+        content=\"text/html; charset=euc-kr\"></head><body>".to_vec();
+    bytes.extend_from_slice(&body);
+    let file = decode_html(&bytes, Some("text/html"));
+    assert!(file.text.ends_with("<p>샘플</p>"));
+
+    // broken utf-8 is replaced instead of failing the whole page
+    let file = decode_html(b"<meta charset=\"utf-8\"><p>ok \xff</p>", None);
+    assert_eq!(file.text, "<meta charset=\"utf-8\"><p>ok \u{fffd}</p>");
+    assert!(file.lossy);
+
+    let file = decode_html(b"<p>ok</p>", Some("text/html; charset=klingon"));
+    assert_eq!(file.unknown.as_deref(), Some("klingon"));
+    assert_eq!(file.text, "<p>ok</p>");
+}
+
+#[tokio::test]
+async fn image_stream() {
+    use hyper::{Body, Response};
+    use futures_util::StreamExt;
+
+    let res = Response::new(Body::from(vec![7u8; 1000]));
+    let mut stream = ImageStream::new(res, None, None);
+
+    assert_eq!(stream.content_length(), Some(1000));
+    assert_eq!(stream.next().await.unwrap().unwrap().len(), 1000);
+    assert!(stream.next().await.is_none());
+
+    let res = Response::new(Body::from(vec![7u8; 1000]));
+    let mut file = Vec::new();
+    let written = ImageStream::new(res, None, None).write_to(&mut file).await.unwrap();
+
+    assert_eq!(written, 1000);
+    assert_eq!(file, vec![7u8; 1000]);
+
+    // a part of the image counts the whole of it
+    let res = Response::builder()
+        .status(206)
+        .header("Content-Range", "bytes 600-999/1000")
+        .body(Body::from(vec![7u8; 400]))
+        .unwrap();
+
+    assert_eq!(ImageStream::new(res, None, None).content_length(), Some(1000));
+}
+
+#[tokio::test]
+async fn image_stream_stall() {
+    use std::time::Duration;
+    use hyper::{Body, Response};
+    use futures_util::StreamExt;
+    use crate::Error;
+
+    // a server which sends nothing after the headers
+    let (_sender, body) = Body::channel();
+    let res = Response::new(body);
+    let mut stream = ImageStream::new(res, None, Some(Duration::from_millis(20)));
+
+    assert!(matches!(stream.next().await, Some(Err(Error::Timeout))));
+}
+
+// serve `handler` on a random local port, returning the address;
+// the handler gets the request with its whole body read
+async fn serve<F>(handler: F) -> std::net::SocketAddr
+where
+    F: Fn(hyper::Request<bytes::Bytes>) -> hyper::Response<hyper::Body>
+        + Send + Sync + 'static,
+{
+    use std::sync::Arc;
+    use std::convert::Infallible;
+    use hyper::Server;
+    use hyper::service::{make_service_fn, service_fn};
+
+    let handler = Arc::new(handler);
+    let make = make_service_fn(move |_| {
+        let handler = handler.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: hyper::Request<hyper::Body>| {
+                let handler = handler.clone();
+
+                async move {
+                    let (parts, body) = req.into_parts();
+                    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+
+                    Ok::<_, Infallible>(handler(hyper::Request::from_parts(parts, body)))
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    addr
+}
+
+// a fresh directory under the system's temp dir
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("lude-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// serve a 1000 bytes long image which supports range requests if `ranges`
+async fn image_server(ranges: bool) -> (std::net::SocketAddr, Vec<u8>) {
+    use hyper::{Body, Response, StatusCode};
+
+    let image = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let data = image.clone();
+
+    let addr = serve(move |req| {
+        let range = req.headers()
+            .get("Range")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes="))
+            .and_then(|value| value.strip_suffix('-'))
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|_| ranges);
+
+        let if_range = req.headers().get("If-Range").map(|value| value.as_bytes().to_vec());
+
+        match range {
+            Some(start) if if_range.as_deref().map_or(true, |etag| etag == b"\"v1\"") => {
+                Response::builder()
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header("ETag", "\"v1\"")
+                    .header("Content-Range", format!("bytes {}-999/1000", start))
+                    .body(Body::from(data[start..].to_vec()))
+                    .unwrap()
+            },
+            _ => {
+                Response::builder()
+                    .header("ETag", "\"v1\"")
+                    .body(Body::from(data.clone()))
+                    .unwrap()
+            },
+        }
+    }).await;
+
+    (addr, image)
+}
+
+#[tokio::test]
+async fn resume_download() {
+    use super::client::ClientBuilder;
+
+    let (addr, image) = image_server(true).await;
+    let client = ClientBuilder::new().detour(false).build().unwrap();
+    let dir = temp_dir("resume");
+    let path = dir.join("1.jpg");
+
+    // pretend the last attempt stopped at 400 bytes; they're marked
+    // so that we can tell they're kept instead of downloaded again
+    std::fs::write(dir.join("1.jpg.part"), vec![0xaa; 400]).unwrap();
+    std::fs::write(dir.join("1.jpg.part.meta"), "\"v1\"\n1000\n").unwrap();
+
+    let dest = format!("http://{}/image.jpg", addr).parse().unwrap();
+    let written = client.download(dest, "/", &path, None, client.retry()).await.unwrap();
+
+    assert_eq!(written, 1000);
+
+    let file = std::fs::read(&path).unwrap();
+    assert_eq!(file[..400], [0xaa; 400][..]);
+    assert_eq!(file[400..], image[400..]);
+    assert!(!dir.join("1.jpg.part").exists());
+    assert!(!dir.join("1.jpg.part.meta").exists());
+}
+
+#[tokio::test]
+async fn resume_checked_download() {
+    use super::client::ClientBuilder;
+    use crate::Error;
+
+    // sha1 of the image served starts with this
+    let hash = "c9c960a0b9";
+    let (addr, image) = image_server(true).await;
+    let client = ClientBuilder::new().detour(false).build().unwrap();
+    let dir = temp_dir("resume-checked");
+    let dest: hyper::Uri = format!("http://{}/image.jpg", addr).parse().unwrap();
+
+    // what the last attempt left is read over, then carried on from
+    std::fs::write(dir.join("1.jpg.part"), &image[..400]).unwrap();
+    std::fs::write(dir.join("1.jpg.part.meta"), "\"v1\"\n1000\n").unwrap();
+
+    let path = dir.join("1.jpg");
+    let written = client.download(dest.clone(), "/", &path, Some(hash), client.retry()).await;
+    assert_eq!(written.unwrap(), 1000);
+    assert_eq!(std::fs::read(dir.join("1.jpg")).unwrap(), image);
+
+    // and so is a part which is already whole
+    std::fs::write(dir.join("2.jpg.part"), &image).unwrap();
+    std::fs::write(dir.join("2.jpg.part.meta"), "\"v1\"\n1000\n").unwrap();
+
+    let path = dir.join("2.jpg");
+    let written = client.download(dest.clone(), "/", &path, Some(hash), client.retry()).await;
+    assert_eq!(written.unwrap(), 1000);
+
+    // what was there is wrong, so the whole is thrown away
+    std::fs::write(dir.join("3.jpg.part"), vec![0xaa; 400]).unwrap();
+    std::fs::write(dir.join("3.jpg.part.meta"), "\"v1\"\n1000\n").unwrap();
+
+    let res = client.download(dest, "/", &dir.join("3.jpg"), Some(hash), &RetryPolicy::never()).await;
+    assert!(matches!(res, Err(Error::Integrity { .. })));
+    assert!(!dir.join("3.jpg").exists());
+    assert!(!dir.join("3.jpg.part").exists());
+}
+
+#[tokio::test]
+async fn resume_download_fallback() {
+    use super::client::ClientBuilder;
+
+    let client = ClientBuilder::new().detour(false).build().unwrap();
+    let dir = temp_dir("fallback");
+
+    // the server ignores ranges; the part must be thrown away
+    let (addr, image) = image_server(false).await;
+    let dest = format!("http://{}/image.jpg", addr).parse().unwrap();
+    std::fs::write(dir.join("1.jpg.part"), vec![0xff; 400]).unwrap();
+
+    client.download(dest, "/", &dir.join("1.jpg"), None, client.retry()).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("1.jpg")).unwrap(), image);
+
+    // the image has changed since (different etag)
+    let (addr, image) = image_server(true).await;
+    let dest = format!("http://{}/image.jpg", addr).parse().unwrap();
+    std::fs::write(dir.join("2.jpg.part"), vec![0xff; 400]).unwrap();
+    std::fs::write(dir.join("2.jpg.part.meta"), "\"v0\"\n1000\n").unwrap();
+
+    client.download(dest, "/", &dir.join("2.jpg"), None, client.retry()).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("2.jpg")).unwrap(), image);
+}
+
+#[test]
+fn cookie_jar() {
+    let mut jar = CookieJar::new();
+    let page: hyper::Uri = "https://e-hentai.org/g/1088955/6a0c6c0a49/".parse().unwrap();
+    let forums: hyper::Uri = "https://forums.e-hentai.org/index.php".parse().unwrap();
+
+    jar.store(&page, "sk=abc; expires=Sun, 18-Oct-2099 12:00:00 GMT; path=/; domain=.e-hentai.org");
+    jar.store(&page, "nw=1; path=/g");
+    jar.store(&page, "evil=1; domain=example.com");
+    jar.store(&page, "tld=1; domain=.org");
+    jar.store(&page, "gone=1; max-age=0");
+
+    assert_eq!(jar.header(&page).as_deref(), Some("nw=1; sk=abc"));
+    assert_eq!(jar.header(&forums).as_deref(), Some("sk=abc"));
+    assert!(jar.get("e-hentai.org", "evil").is_none());
+    assert!(jar.get("e-hentai.org", "tld").is_none());
+    assert!(jar.get("e-hentai.org", "gone").is_none());
+    assert!(jar.get("forums.e-hentai.org", "sk").unwrap().expires().is_some());
+    assert!(jar.get("forums.e-hentai.org", "nw").is_none());
+    assert!(jar.get("exhentai.org", "sk").is_none());
+
+    // the host itself may be named, even with a single label
+    let local: hyper::Uri = "http://localhost/".parse().unwrap();
+    jar.store(&local, "dev=1; domain=localhost");
+    assert!(jar.get("localhost", "dev").is_some());
+    assert!(jar.get("e-hentai.org", "dev").is_none());
+
+    // a later one replaces it, and an expired one removes it
+    jar.store(&page, "nw=2; path=/g");
+    jar.store(&page, "sk=; max-age=-1; path=/; domain=e-hentai.org");
+    assert_eq!(jar.header(&page).as_deref(), Some("nw=2"));
+
+    jar.insert(Cookie::new("e-hentai.org", "ipb_member_id", "42"));
+    let path = temp_dir("cookies").join("cookies.txt");
+    jar.save(&path).unwrap();
+
+    let jar = CookieJar::load(&path).unwrap();
+    assert_eq!(jar.header(&page).as_deref(), Some("nw=2; ipb_member_id=42"));
+    assert_eq!(jar.header(&forums).as_deref(), Some("ipb_member_id=42"));
+
+    // expiries too far away to represent are taken as none at all
+    let mut jar = CookieJar::new();
+    jar.store(&page, &format!("far=1; max-age={}", i64::MAX));
+    assert!(jar.get("e-hentai.org", "far").unwrap().expires().is_none());
+
+    std::fs::write(&path, format!(".e-hentai.org\tTRUE\t/\tFALSE\t{}\tfar\t1\n", u64::MAX)).unwrap();
+    let jar = CookieJar::load(&path).unwrap();
+    assert!(jar.get("e-hentai.org", "far").unwrap().expires().is_none());
+}
+
+#[tokio::test]
+async fn session_cookies() {
+    use hyper::Response;
+    use hyper::header::{COOKIE, SET_COOKIE};
+    use super::client::ClientBuilder;
+
+    // echo the cookies back, and hand out a new one
+    let addr = serve(|req| {
+        let cookie = req.headers()
+            .get(COOKIE)
+            .map(|value| value.to_str().unwrap().to_owned())
+            .unwrap_or_default();
+
+        Response::builder()
+            .header(SET_COOKIE, "sk=xyz; path=/")
+            .body(format!("<p>{}</p>", cookie).into())
+            .unwrap()
+    }).await;
+
+    let client = ClientBuilder::new()
+        .detour(false)
+        .cookies("42", "hash")
+        .build()
+        .unwrap();
+
+    let dest: hyper::Uri = format!("http://{}/", addr).parse().unwrap();
+
+    // the member cookies belong to the site, not to us
+    let doc = client.get_html(dest.clone()).await.unwrap();
+    assert_eq!(doc.find(select::predicate::Name("p")).next().unwrap().text(), "");
+
+    let doc = client.get_html(dest).await.unwrap();
+    assert_eq!(doc.find(select::predicate::Name("p")).next().unwrap().text(), "sk=xyz");
+    assert_eq!(client.cookies().get("e-hentai.org", "ipb_member_id").unwrap().value(), "42");
+}
+
+#[tokio::test]
+async fn login() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use hyper::Response;
+    use hyper::header::SET_COOKIE;
+    use crate::Error;
+
+    let addr = serve(|req| {
+        let form = String::from_utf8_lossy(req.body()).into_owned();
+        let error = |message: &str| format!(
+            "<div class=\"errorwrap\"><h4>The error returned was:</h4><p>{}</p></div>",
+            message
+        );
+
+        if form.contains("UserName=robot") {
+            return Response::new("<form><div class=\"g-recaptcha\"></div></form>".into());
+        }
+
+        if !form.contains("UserName=alice") || !form.contains("PassWord=p%40ss+word") {
+            return Response::new(error("Username or password incorrect").into());
+        }
+
+        Response::builder()
+            .status(302)
+            .header("Location", "/index.php")
+            .header(SET_COOKIE, "ipb_member_id=42; path=/; expires=Sun, 18-Oct-2099 12:00:00 GMT")
+            .header(SET_COOKIE, "ipb_pass_hash=0123abcd; path=/; expires=Sun, 18-Oct-2099 12:00:00 GMT")
+            .body(hyper::Body::empty())
+            .unwrap()
+    }).await;
+
+    let login = |username: &'static str, password: &'static str| {
+        Explorer::builder()
+            .detour(false)
+            .login_url(&format!("http://{}/index.php?act=Login&CODE=01", addr))
+            .login(username, password)
+    };
+
+    let explorer = login("alice", "p@ss word").await.unwrap();
+    let page: hyper::Uri = "https://e-hentai.org/".parse().unwrap();
+    assert_eq!(
+        explorer.cookies().header(&page).as_deref(),
+        Some("ipb_member_id=42; ipb_pass_hash=0123abcd")
+    );
+
+    assert!(matches!(login("alice", "wrong").await, Err(Error::WrongCredentials)));
+    assert!(matches!(login("robot", "beep").await, Err(Error::CaptchaRequired)));
+
+    // a failed attempt is retried, as the identity whose cookies are looked at
+    // afterwards, even with a pool to rotate through
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = attempts.clone();
+
+    let flaky = serve(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+        0 => Response::builder().status(503).body(hyper::Body::empty()).unwrap(),
+        _ => Response::builder()
+            .status(302)
+            .header("Location", "/index.php")
+            .header(SET_COOKIE, "ipb_member_id=42; path=/")
+            .header(SET_COOKIE, "ipb_pass_hash=0123abcd; path=/")
+            .body(hyper::Body::empty())
+            .unwrap(),
+    }).await;
+
+    let explorer = Explorer::builder()
+        .detour(false)
+        .retry(RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(1)))
+        .identity(Identity::new("first"))
+        .identity(Identity::new("second"))
+        .login_url(&format!("http://{}/index.php?act=Login&CODE=01", flaky))
+        .login("alice", "p@ss word")
+        .await
+        .unwrap();
+
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        explorer.cookies().header(&page).as_deref(),
+        Some("ipb_member_id=42; ipb_pass_hash=0123abcd")
+    );
+}
+
+#[tokio::test]
+async fn exhentai() {
+    use std::sync::{Arc, Mutex};
+    use hyper::Response;
+    use hyper::header::COOKIE;
+    use crate::Error;
+
+    let explorer = Explorer::builder()
+        .exhentai(true)
+        .cookies("42", "0123abcd")
+        .igneous("fedcba")
+        .build()
+        .unwrap();
+
+    let page: hyper::Uri = "https://exhentai.org/g/1088955/4464b39d07/".parse().unwrap();
+    assert_eq!(
+        explorer.cookies().header(&page).as_deref(),
+        Some("ipb_member_id=42; ipb_pass_hash=0123abcd; igneous=fedcba")
+    );
+
+    // a stand-in which lets only those with igneous in
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let log = requested.clone();
+    let addr = serve(move |req| {
+        log.lock().unwrap().push(req.uri().to_string());
+
+        let allowed = req.headers()
+            .get(COOKIE)
+            .map_or(false, |value| value.to_str().unwrap().contains("igneous="));
+
+        Response::new(if allowed { GALLERY } else { "" }.into())
+    }).await;
+
+    let base = format!("http://{}/", addr);
+    let gallery = format!("{}g/1088955/4464b39d07/", base);
+    let builder = || Explorer::builder().detour(false).exhentai(true).base_url(&base);
+
+    let explorer = builder().build().unwrap();
+    let res = explorer.article_from_path(gallery.clone()).await;
+    assert!(matches!(res, Err(Error::Unauthorized)));
+
+    let res = explorer.search("touhou").next().await;
+    assert!(matches!(res, Err(Error::Unauthorized)));
+    assert_eq!(requested.lock().unwrap()[1], "/?page=0&f_search=touhou");
+
+    let mut jar = CookieJar::new();
+    jar.insert(Cookie::new(&addr.ip().to_string(), "igneous", "fedcba"));
+
+    let explorer = builder().cookie_jar(jar).build().unwrap();
+    let article = explorer.article_from_path(gallery).await.unwrap();
+    assert_eq!(article.meta().length, 42);
+}
+
+// a stand-in proxy which wants "user:pass", speaking CONNECT or SOCKS5;
+// returns its address and the targets asked for
+async fn proxy_server(socks: bool)
+    -> (std::net::SocketAddr, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn connect(mut client: TcpStream, log: Arc<Mutex<Vec<String>>>) -> std::io::Result<()> {
+        let mut head = Vec::new();
+
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await?);
+        }
+
+        let head = String::from_utf8(head).unwrap();
+        let target = head.split_whitespace().nth(1).unwrap().to_owned();
+        log.lock().unwrap().push(target.clone());
+
+        if !head.contains(&format!("Proxy-Authorization: Basic {}", base64::encode("user:pass"))) {
+            return client.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+        }
+
+        let mut server = TcpStream::connect(&target).await?;
+        client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await?;
+        tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+
+        Ok(())
+    }
+
+    async fn socks5(mut client: TcpStream, log: Arc<Mutex<Vec<String>>>) -> std::io::Result<()> {
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).await?;
+        client.read_exact(&mut vec![0; buf[1] as usize]).await?;
+        client.write_all(&[5, 2]).await?;
+
+        // username/password auth
+        async fn field(client: &mut TcpStream) -> std::io::Result<String> {
+            let mut buf = vec![0; client.read_u8().await? as usize];
+            client.read_exact(&mut buf).await?;
+            Ok(String::from_utf8(buf).unwrap())
+        }
+
+        let _version = client.read_u8().await?;
+        let username = field(&mut client).await?;
+        let password = field(&mut client).await?;
+        let ok = username == "user" && password == "pass";
+        client.write_all(&[1, if ok { 0 } else { 1 }]).await?;
+
+        if !ok {
+            return Ok(());
+        }
+
+        // CONNECT with an ipv4 address (1) or a domain name (3)
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await?;
+
+        let host = match buf {
+            [5, 1, 0, 1] => {
+                let mut ip = [0; 4];
+                client.read_exact(&mut ip).await?;
+                std::net::Ipv4Addr::from(ip).to_string()
+            },
+            [5, 1, 0, 3] => {
+                let mut host = vec![0; client.read_u8().await? as usize];
+                client.read_exact(&mut host).await?;
+                String::from_utf8(host).unwrap()
+            },
+            _ => panic!("unexpected request {:?}", buf),
+        };
+
+        let port = client.read_u16().await?;
+        let target = format!("{}:{}", host, port);
+        log.lock().unwrap().push(target.clone());
+
+        let mut server = TcpStream::connect(&target).await?;
+        client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+
+        Ok(())
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let log = targets.clone();
+
+    tokio::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let log = log.clone();
+
+            tokio::spawn(async move {
+                let _ = if socks { socks5(client, log).await } else { connect(client, log).await };
+            });
+        }
+    });
+
+    (addr, targets)
+}
+
+#[tokio::test]
+async fn proxy() {
+    use std::time::Duration;
+    use hyper::Response;
+    use super::client::ClientBuilder;
+
+    let addr = serve(|_| Response::new("<p>hello</p>".into())).await;
+    let dest = || format!("http://localhost:{}/", addr.port()).parse::<hyper::Uri>().unwrap();
+
+    // socks5 resolves the host here, socks5h leaves it to the proxy
+    for (scheme, host) in [("http", "localhost"), ("socks5", "127.0.0.1"), ("socks5h", "localhost")] {
+        let (proxy_addr, targets) = proxy_server(scheme != "http").await;
+        let url = format!("{}://user:pass@{}", scheme, proxy_addr);
+
+        let client = ClientBuilder::new()
+            .detour(false)
+            .retry(RetryPolicy::never())
+            .proxy(Proxy::parse(&url).unwrap())
+            .build()
+            .unwrap();
+
+        client.get_html(dest()).await.unwrap();
+        assert_eq!(targets.lock().unwrap()[0], format!("{}:{}", host, addr.port()));
+
+        // wrong credentials
+        let client = ClientBuilder::new()
+            .detour(false)
+            .retry(RetryPolicy::never())
+            .read_timeout(Duration::from_secs(5))
+            .proxy(Proxy::parse(&url.replace("pass@", "fail@")).unwrap())
+            .build()
+            .unwrap();
+
+        assert!(client.get_html(dest()).await.is_err());
+    }
+
+    // only pages go through the proxy, so it's never asked for the image server
+    let (proxy_addr, targets) = proxy_server(false).await;
+    let client = ClientBuilder::new()
+        .detour(false)
+        .page_proxy(Some(Proxy::http(&proxy_addr.to_string()).auth("user", "pass")))
+        .image_proxy(None)
+        .build()
+        .unwrap();
+
+    client.get_html(dest()).await.unwrap();
+    assert!(targets.lock().unwrap().is_empty());
+
+    // and the other way around; this one isn't even listening
+    let client = ClientBuilder::new()
+        .detour(false)
+        .image_proxy(Some(Proxy::http("127.0.0.1:1")))
+        .retry(RetryPolicy::never())
+        .build()
+        .unwrap();
+
+    assert!(client.get_html(dest()).await.is_err());
+
+    assert!(Proxy::parse("ftp://proxy:21").is_err());
+    assert!(Proxy::parse("http://proxy").is_err());
+}
+
+#[tokio::test]
+async fn identity_pool() {
+    use std::time::Duration;
+    use hyper::Response;
+    use hyper::header::COOKIE;
+    use crate::Error;
+
+    // member 1 is banned, member 2 is fine, member 3 has no quota left
+    let addr = serve(|req| {
+        let cookie = req.headers()
+            .get(COOKIE)
+            .map(|value| value.to_str().unwrap().to_owned())
+            .unwrap_or_default();
+
+        let body = if cookie.contains("ipb_member_id=1") {
+            String::from("Your IP address has been temporarily banned for excessive \
+                pageloads. The ban expires in 2 hours")
+        } else if cookie.contains("ipb_member_id=3") {
+            String::from("<img id=\"img\" src=\"https://ehgt.org/g/509.gif\">")
+        } else {
+            String::from(GALLERY)
+        };
+
+        Response::new(body.into())
+    }).await;
+
+    let member = |name: &str, id: &str| {
+        let mut jar = CookieJar::new();
+        jar.insert(Cookie::new("127.0.0.1", "ipb_member_id", id));
+        Identity::new(name).cookie_jar(jar)
+    };
+
+    let explorer = Explorer::builder()
+        .detour(false)
+        .retry(RetryPolicy::never())
+        .identity(member("banned", "1"))
+        .identity(member("fine", "2"))
+        .identity(member("exhausted", "3"))
+        .build()
+        .unwrap();
+
+    let gallery = format!("http://{}/g/1088955/4464b39d07/", addr);
+
+    for _ in 0..4 {
+        explorer.article_from_path(gallery.clone()).await.unwrap();
+    }
+
+    let stats = explorer.identities();
+    assert_eq!(stats.iter().map(|s| s.requests).collect::<Vec<_>>(), [1, 4, 1]);
+    assert_eq!(stats.iter().map(|s| s.retirements).collect::<Vec<_>>(), [1, 0, 1]);
+
+    // the ban says how long; the quota doesn't
+    let banned = stats[0].retired_for.unwrap();
+    assert!(banned > Duration::from_secs(119 * 60) && banned <= Duration::from_secs(120 * 60));
+    assert!(stats[1].retired_for.is_none());
+    assert!(stats[2].retired_for.unwrap() <= Duration::from_secs(60 * 60));
+
+    // once everyone is out, the last error is given, and then how long to wait
+    let explorer = Explorer::builder()
+        .detour(false)
+        .retry(RetryPolicy::never())
+        .retire_for(Duration::from_millis(100))
+        .identity(member("banned", "1"))
+        .identity(member("exhausted", "3"))
+        .build()
+        .unwrap();
+
+    let res = explorer.article_from_path(gallery.clone()).await;
+    assert!(matches!(res, Err(Error::QuotaExceeded) | Err(Error::Banned(_))));
+
+    let res = explorer.article_from_path(gallery.clone()).await;
+    assert!(matches!(res, Err(Error::RateLimited(Some(wait))) if wait <= Duration::from_millis(100)));
+
+    // the one without a ban is back after retire_for
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let res = explorer.article_from_path(gallery.clone()).await;
+    assert!(matches!(res, Err(Error::QuotaExceeded)));
+
+    // a retirement too long to count is cut short instead of panicking
+    let explorer = Explorer::builder()
+        .detour(false)
+        .retry(RetryPolicy::never())
+        .retire_for(Duration::MAX)
+        .identity(member("exhausted", "3"))
+        .build()
+        .unwrap();
+
+    assert!(explorer.article_from_path(gallery).await.is_err());
+    let retired = explorer.identities()[0].retired_for.unwrap();
+    assert!(retired > Duration::from_secs(364 * 24 * 60 * 60));
+}
+
+#[tokio::test]
+async fn memory_transport() {
+    use std::sync::Arc;
+    use hyper::header::REFERER;
+    use crate::Error;
+
+    let thumb = "https://ehgt.org/5f/6e/5f6e0b7d1c2a3e4f5a6b7c8d9e0f1a2b3c4d5e6f-1234567-1280-1810-jpg_250.jpg";
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let image = "https://abcd.hath.network/h/7037807198/keystamp=1;fileindex=1/001.jpg";
+
+    let transport = Arc::new(MemoryTransport::new()
+        .html(URL, GALLERY)
+        .html(&format!("{}?p=1", URL), GALLERY)
+        .html(page, &format!("<div id=\"i3\"><img id=\"img\" src=\"{}\"></div>", image))
+        .image(image, vec![1u8, 2, 3])
+        .image(thumb, vec![4u8]));
+
+    let explorer = Explorer::builder()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .build()
+        .unwrap();
+
+    let mut article = explorer.article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.meta().title, "[Sample Circle] Sample Gallery (Original) [Korean]");
+    assert_eq!(article.comments().count(), 3);
+    assert_eq!(article.load_thumb().await.unwrap(), [4]);
+
+    // 42 pages are split into two pages of the gallery
+    article.load_image_list().await.unwrap();
+    assert_eq!(article.load_image(0).await.unwrap(), [1, 2, 3]);
+
+    // the image is asked for as shown in its page
+    let requests = transport.requests();
+    let last = requests.last().unwrap();
+    assert_eq!(last.uri().to_string(), image);
+    assert_eq!(last.headers()[REFERER], page);
+
+    // the second page isn't there
+    let res = article.load_image(1).await;
+    assert!(matches!(res, Err(Error::NotFound)));
+}
+
+#[tokio::test]
+async fn record_replay() {
+    use std::sync::Arc;
+    use super::record::normalize;
+    use crate::Error;
+
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let image = "https://abcd.hath.network/h/7037807198/1.jpg";
+    let dir = temp_dir("fixtures");
+
+    let transport = Arc::new(MemoryTransport::new()
+        .respond(URL, hyper::StatusCode::OK, &[
+            ("Content-Type", "text/html; charset=UTF-8"),
+            ("Set-Cookie", "sk=secret; Path=/"),
+        ], GALLERY)
+        .html(page, &format!("<img id=\"img\" src=\"{}\">", image))
+        .image(image, vec![1u8, 2, 3]));
+
+    let explorer = Explorer::builder()
+        .transport(transport)
+        .cookies("1234", "f0e1d2c3b4a5")
+        .record(&dir)
+        .build()
+        .unwrap();
+
+    let article = explorer.article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.load_image(0).await.unwrap(), [1, 2, 3]);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 6);
+
+    // the credentials stay out of the fixtures
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let text = String::from_utf8_lossy(&std::fs::read(entry.unwrap().path()).unwrap()).into_owned();
+        assert!(!text.contains("f0e1d2c3b4a5") && !text.contains("secret"));
+        assert!(!text.to_lowercase().contains("cookie:"));
+    }
+
+    // nothing but the fixtures from now on
+    let explorer = Explorer::builder().replay(&dir).build().unwrap();
+
+    let article = explorer.article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.meta().length, 42);
+    assert_eq!(article.load_image(0).await.unwrap(), [1, 2, 3]);
+
+    let res = explorer.article_from_path(format!("{}?p=1", URL)).await;
+    assert!(matches!(res, Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound));
+
+    // the same url written differently
+    let a = "HTTPS://E-Hentai.org:443/?page=1&f_search=x#top".parse().unwrap();
+    let b = "https://e-hentai.org/?f_search=x&page=1".parse().unwrap();
+    assert_eq!(normalize(&a), normalize(&b));
+}
+
+#[tokio::test]
+async fn page_cache() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use hyper::Uri;
+    use super::client::{Client, ClientBuilder};
+
+    let search = "https://e-hentai.org/?page=0&f_search=sample";
+    let home = "https://e-hentai.org/home.php";
+    let dir = temp_dir("cache");
+
+    assert_eq!(PageKind::of(&URL.parse().unwrap()), Some(PageKind::Gallery));
+    assert_eq!(PageKind::of(&search.parse().unwrap()), Some(PageKind::Search));
+    assert_eq!(PageKind::of(&home.parse().unwrap()), None);
+
+    // image pages would outlive the links in them
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let transport = Arc::new(MemoryTransport::new().html(page, IMAGE));
+    let images = ClientBuilder::new()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .cache(CachePolicy::new(&dir))
+        .build()
+        .unwrap();
+
+    images.get_html(page.parse().unwrap()).await.unwrap();
+    images.get_html(page.parse().unwrap()).await.unwrap();
+    assert_eq!(transport.requests().len(), 2);
+
+    let transport = Arc::new(MemoryTransport::new()
+        .html(URL, GALLERY)
+        .html(search, SEARCH)
+        .html(home, "<html><body>account</body></html>"));
+
+    let client = |policy: CachePolicy| ClientBuilder::new()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .cache(policy)
+        .build()
+        .unwrap();
+
+    let count = || transport.requests().len();
+    async fn get(client: &Client, url: &str) {
+        client.get_html(url.parse::<Uri>().unwrap()).await.unwrap();
+    }
+
+    let cached = client(CachePolicy::new(&dir).ttl(PageKind::Search, Duration::ZERO));
+
+    get(&cached, URL).await;
+    get(&cached, URL).await;
+    assert_eq!(count(), 1);
+
+    // asked not to look in the cache
+    cached.load_html(URL.parse().unwrap(), cached.retry(), true).await.unwrap();
+    assert_eq!(count(), 2);
+
+    // neither search results (ttl of zero) nor account pages are kept
+    get(&cached, search).await;
+    get(&cached, search).await;
+    get(&cached, home).await;
+    get(&cached, home).await;
+    assert_eq!(count(), 6);
+
+    // another session finds the pages of the previous one
+    let again = client(CachePolicy::new(&dir));
+    get(&again, URL).await;
+    assert_eq!(count(), 6);
+
+    // but someone logged in sees other pages
+    again.set_cookies("1234", "abcd");
+    get(&again, URL).await;
+    assert_eq!(count(), 7);
+
+    // room for only one page; the least recently used one goes
+    let small = client(CachePolicy::new(&dir).max_size(GALLERY.len() as u64 + 256));
+    get(&small, URL).await;
+    get(&small, search).await;
+    get(&small, URL).await;
+    assert_eq!(count(), 10);
+    get(&small, URL).await;
+    assert_eq!(count(), 10);
+
+    // articles can bypass the cache as well, for everything they load
+    let dir = temp_dir("cache-bypass");
+    let explorer = Explorer::builder()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .cache(CachePolicy::new(&dir))
+        .build()
+        .unwrap();
+
+    explorer.article_from_path(String::from(URL)).await.unwrap();
+    explorer.article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(count(), 11);
+
+    let mut article = explorer.fresh_article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(count(), 12);
+    article.load_image_list().await.unwrap_err();
+    article.load_image_list().await.unwrap_err();
+    assert_eq!(count(), 14);
+
+    // a bypassing call isn't handed what another one found in the cache
+    let slow = Arc::new(Slow {
+        inner: transport.clone(),
+        delay: Duration::from_millis(50),
+    });
+
+    let cached = ClientBuilder::new()
+        .transport(slow)
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .cache(CachePolicy::new(&dir))
+        .build()
+        .unwrap();
+
+    let (a, b) = tokio::join!(
+        cached.load_html(URL.parse().unwrap(), cached.retry(), false),
+        cached.load_html(URL.parse().unwrap(), cached.retry(), true)
+    );
+    a.unwrap();
+    b.unwrap();
+    assert_eq!(count(), 15);
+
+    // a page which turns out not to be fine isn't kept
+    let removed = "https://e-hentai.org/g/1/0000000000/";
+    let transport = Arc::new(MemoryTransport::new().html(
+        removed,
+        "<html><head><title>Gallery Not Available - E-Hentai Galleries</title></head>\
+        <body><div class=\"d\"><p>This gallery has been removed or is unavailable.</p></div></body></html>"
+    ));
+
+    let dir = temp_dir("cache-removed");
+    let cached = ClientBuilder::new()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .cache(CachePolicy::new(&dir))
+        .build()
+        .unwrap();
+
+    assert!(cached.get_html(removed.parse().unwrap()).await.is_err());
+    assert!(cached.get_html(removed.parse().unwrap()).await.is_err());
+    assert_eq!(transport.requests().len(), 2);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+// answers like `inner`, after a while
+struct Slow {
+    inner: std::sync::Arc<dyn Transport>,
+    delay: std::time::Duration,
+}
+
+impl Transport for Slow {
+    fn send(&self, req: hyper::Request<hyper::Body>)
+        -> BoxFuture<'_, Result<hyper::Response<hyper::Body>, crate::Error>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.delay).await;
+            self.inner.send(req).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn coalesce_requests() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::Error;
+
+    let thumb = "https://ehgt.org/5f/6e/5f6e0b7d1c2a3e4f5a6b7c8d9e0f1a2b3c4d5e6f-1234567-1280-1810-jpg_250.jpg";
+    let missing = "https://e-hentai.org/g/1/0000000000/";
+
+    let memory = Arc::new(MemoryTransport::new()
+        .html(URL, GALLERY)
+        .image(thumb, vec![4u8]));
+
+    let slow = Slow {
+        inner: memory.clone(),
+        delay: Duration::from_millis(50),
+    };
+
+    let explorer = Explorer::builder()
+        .transport(Arc::new(slow))
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .build()
+        .unwrap();
+
+    let (a, b) = tokio::join!(
+        explorer.article_from_path(String::from(URL)),
+        explorer.article_from_path(String::from(URL))
+    );
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert_eq!(a.meta().title, b.meta().title);
+    assert_eq!(b.comments().count(), 3);
+    assert_eq!(memory.requests().len(), 1);
+
+    let (x, y, z) = tokio::join!(a.load_thumb(), b.load_thumb(), a.load_thumb());
+    assert_eq!((x.unwrap(), y.unwrap(), z.unwrap()), (vec![4], vec![4], vec![4]));
+    assert_eq!(memory.requests().len(), 2);
+
+    // a failure is shared as well
+    let (a, b) = tokio::join!(
+        explorer.article_from_path(String::from(missing)),
+        explorer.article_from_path(String::from(missing))
+    );
+    assert!(matches!(a.as_ref().map_err(Error::inner), Err(Error::NotFound)));
+    assert!(matches!(b.as_ref().map_err(Error::inner), Err(Error::NotFound)));
+    assert!(matches!(b, Err(Error::Shared(_))));
+    assert_eq!(memory.requests().len(), 3);
+
+    // one after another isn't coalesced
+    explorer.article_from_path(String::from(URL)).await.unwrap();
+    explorer.article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(memory.requests().len(), 5);
+}
+
+#[tokio::test]
+async fn image_failover() {
+    use std::sync::Arc;
+    use select::document::Document;
+    use crate::Error;
+
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let dead = "https://dead.hath.network/h/7037807198/001.jpg";
+    let slow = "https://slow.hath.network/h/7037807198/001.jpg";
+    let alive = "https://alive.hath.network/h/7037807198/001.jpg";
+
+    let image_page = |src: &str, key: &str| format!(
+        "<div id=\"i3\"><img id=\"img\" src=\"{}\"></div>\
+        <div id=\"i6\"><a href=\"#\" id=\"loadfail\" onclick=\"return nl('{}')\">Reload broken image</a></div>",
+        src, key
+    );
+
+    assert_eq!(parser::reload_key(&Document::from(IMAGE)).as_deref(), Some("12345-67890"));
+    assert_eq!(parser::reload_key(&Document::from(GALLERY)), None);
+
+    // the first two servers fail; the keys pile up as in a browser
+    let transport = Arc::new(MemoryTransport::new()
+        .html(URL, GALLERY)
+        .html(page, &image_page(dead, "1-1"))
+        .html(&format!("{}?nl=1-1", page), &image_page(slow, "2-2"))
+        .respond(slow, hyper::StatusCode::BAD_GATEWAY, &[], "")
+        .html(&format!("{}?nl=1-1&nl=2-2", page), &image_page(alive, "3-3"))
+        .image(alive, vec![1u8, 2, 3]));
+
+    let explorer = |hops: u32| Explorer::builder()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .retry(RetryPolicy::never())
+        .failover(hops)
+        .build()
+        .unwrap();
+
+    let article = explorer(2).article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.load_image(0).await.unwrap(), [1, 2, 3]);
+
+    // the image is asked for from the page it was shown in
+    let requests = transport.requests();
+    let last = requests.last().unwrap();
+    assert_eq!(last.uri().to_string(), alive);
+    assert_eq!(last.headers()[hyper::header::REFERER], format!("{}?nl=1-1&nl=2-2", page).as_str());
+
+    // not enough hops to reach the working one
+    let article = explorer(1).article_from_path(String::from(URL)).await.unwrap();
+    let res = article.load_image(0).await;
+    assert!(matches!(res, Err(Error::Status(status)) if status.as_u16() == 502));
+}
+
+#[tokio::test]
+async fn image_stall() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use hyper::Response;
+
+    // accepts connections, but never answers
+    let stalled = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stalled_addr = stalled.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+
+        while let Ok((socket, _)) = stalled.accept().await {
+            held.push(socket);
+        }
+    });
+
+    let image_page = |src: String, key: &str| format!(
+        "<div id=\"i3\"><img id=\"img\" src=\"{}\"></div>\
+        <div id=\"i6\"><a href=\"#\" id=\"loadfail\" onclick=\"return nl('{}')\">Reload broken image</a></div>",
+        src, key
+    );
+
+    // the server doesn't know its own address until it's up
+    let me = Arc::new(Mutex::new(String::new()));
+    let addr = serve({
+        let me = me.clone();
+
+        move |req| {
+            let me = me.lock().unwrap().clone();
+
+            let body = match (req.uri().path(), req.uri().query()) {
+                ("/h/7037807198/001.jpg", _) => return Response::new(vec![1u8, 2, 3].into()),
+                ("/s/7037807198/1088955-1", Some("nl=1-1")) => {
+                    image_page(format!("http://{}/h/7037807198/001.jpg", me), "2-2")
+                },
+                ("/s/7037807198/1088955-1", _) => {
+                    image_page(format!("http://{}/h/7037807198/001.jpg", stalled_addr), "1-1")
+                },
+                _ => GALLERY.replace("https://e-hentai.org/s/", &format!("http://{}/s/", me)),
+            };
+
+            Response::new(body.into())
+        }
+    }).await;
+
+    *me.lock().unwrap() = addr.to_string();
+
+    let explorer = Explorer::builder()
+        .detour(false)
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .retry(RetryPolicy::never())
+        .read_timeout(Duration::from_millis(200))
+        .failover(1)
+        .build()
+        .unwrap();
+
+    let url = format!("http://{}/g/1088955/4464b39d07/", addr);
+    let article = explorer.article_from_path(url).await.unwrap();
+
+    let image = tokio::time::timeout(Duration::from_secs(5), article.load_image(0)).await;
+    assert_eq!(image.unwrap().unwrap(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn original_image() {
+    use std::sync::Arc;
+    use hyper::StatusCode;
+    use crate::Error;
+
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let resampled = "https://abcd.hath.network/h/7037807198/001.jpg";
+    let full = "https://e-hentai.org/fullimg/1088955/1/7037807198/001.jpg";
+    let original = "https://efgh.hath.network/om/7037807198/001.jpg";
+
+    let image_page = format!(
+        "<div id=\"i2\"><div class=\"sn\"><a id=\"prev\" href=\"{0}\"></a>\
+        <div><span>1</span> / <span>42</span></div><a id=\"next\" href=\"{0}\"></a></div>\
+        <div>001.jpg :: 1280 x 1810 :: 412.5 KiB</div></div>\
+        <div id=\"i3\"><img id=\"img\" src=\"{1}\"></div>\
+        <div id=\"i6\"><a href=\"{2}\">Download original 2560 x 3620 3.1 MiB source</a></div>",
+        page, resampled, full
+    );
+
+    let home = "https://e-hentai.org/home.php";
+    let used_up = HOME.replace("1,234", "5,000");
+
+    let transport = |answer: MemoryTransport| Arc::new(answer
+        .html(URL, GALLERY)
+        .html(page, &image_page)
+        .image(resampled, vec![1u8])
+        .image(original, vec![1u8, 2, 3]));
+
+    assert!(matches!(parser::original_notice(NOTICE_GP), Error::GpRequired));
+    assert!(matches!(parser::original_notice(NOTICE_LOGIN), Error::Unauthorized));
+    assert!(matches!(parser::original_notice(NOTICE_LIMIT), Error::QuotaExceeded));
+    assert!(matches!(parser::original_notice("<p>limit of 5 gp</p>"), Error::Parse(_)));
+
+    let article = |transport: Arc<MemoryTransport>, logged_in: bool| async move {
+        let builder = Explorer::builder()
+            .transport(transport)
+            .rate_limits(RateLimit::unlimited(), RateLimit::unlimited());
+
+        let builder = if logged_in { builder.cookies("1234", "abcd") } else { builder };
+        builder.build().unwrap().article_from_path(String::from(URL)).await.unwrap()
+    };
+
+    // the link sends us to where the original is
+    let free = transport(MemoryTransport::new()
+        .html(home, HOME)
+        .respond(full, StatusCode::FOUND, &[("Location", original)], ""));
+
+    let a = article(free.clone(), true).await;
+    assert_eq!(a.load_original(0, OriginalPolicy::Resampled).await.unwrap(), [1]);
+    assert_eq!(a.load_original(0, OriginalPolicy::OriginalWhenFree).await.unwrap(), [1, 2, 3]);
+    assert_eq!(a.load_original(0, OriginalPolicy::Original).await.unwrap(), [1, 2, 3]);
+
+    let requests = free.requests();
+    let last = requests.last().unwrap();
+    assert_eq!(last.uri().to_string(), original);
+    assert_eq!(last.headers()[hyper::header::REFERER], page);
+
+    // guests can't have originals
+    let a = article(free, false).await;
+    assert_eq!(a.load_original(0, OriginalPolicy::OriginalWhenFree).await.unwrap(), [1]);
+    assert!(matches!(a.load_original(0, OriginalPolicy::Original).await, Err(Error::Unauthorized)));
+
+    // out of the free quota; the link isn't even followed
+    let costly = transport(MemoryTransport::new()
+        .html(home, &used_up)
+        .respond(full, StatusCode::FOUND, &[("Location", original)], ""));
+
+    let a = article(costly.clone(), true).await;
+    assert_eq!(a.load_original(0, OriginalPolicy::OriginalWhenFree).await.unwrap(), [1]);
+    assert!(costly.requests().iter().all(|req| req.uri() != full));
+
+    // the site says no after all
+    for (notice, err) in [(NOTICE_GP, "GP"), (NOTICE_LIMIT, "quota")] {
+        let refused = transport(MemoryTransport::new()
+            .html(home, HOME)
+            .html(full, notice));
+
+        let a = article(refused, true).await;
+        assert_eq!(a.load_original(0, OriginalPolicy::OriginalWhenFree).await.unwrap(), [1]);
+
+        let res = a.load_original(0, OriginalPolicy::Original).await;
+        assert!(res.unwrap_err().to_string().contains(err));
+    }
+}
+
+#[tokio::test]
+async fn image_integrity() {
+    use std::sync::Arc;
+    use futures_util::StreamExt;
+    use super::integrity::{expected_hash, verify, Checksum};
+    use crate::Error;
+
+    // sha1 of [1, 2, 3] starts with 7037807198
+    let page = "https://e-hentai.org/s/7037807198/1088955-1";
+    let broken = "https://broken.hath.network/h/7037807198/001.jpg";
+    let fine = "https://fine.hath.network/h/7037807198/001.jpg";
+
+    assert_eq!(expected_hash(page).as_deref(), Some("7037807198"));
+    assert!(verify("da39a3ee5e6b4b0d3255bfef95601890afd80709", &[]).is_ok());
+    assert!(verify("636e2ec698dac903498e648bd2f3af641d3c88cb", &(0..56).collect::<Vec<u8>>()).is_ok());
+
+    // it doesn't matter how the image arrives in pieces
+    let image = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    for split in [0, 1, 63, 64, 65, 400, 999, 1000] {
+        let mut checksum = Checksum::new("c9c960a0b925474f");
+        checksum.update(&image[..split]);
+        checksum.update(&image[split..]);
+        assert!(checksum.finish().is_ok());
+    }
+    assert_eq!(expected_hash("https://e-hentai.org/g/1088955/0123456789/"), None);
+    assert!(verify("7037807198", &[1, 2, 3]).is_ok());
+    assert!(matches!(verify("7037807198", &[1, 2]), Err(Error::Integrity { .. })));
+
+    let image_page = |src: &str, key: &str| format!(
+        "<div id=\"i3\"><img id=\"img\" src=\"{}\"></div>\
+        <div id=\"i6\"><a href=\"#\" id=\"loadfail\" onclick=\"return nl('{}')\">Reload broken image</a></div>",
+        src, key
+    );
+
+    // the first server sends a truncated image
+    let transport = Arc::new(MemoryTransport::new()
+        .html(URL, GALLERY)
+        .html(page, &image_page(broken, "1-1"))
+        .image(broken, vec![1u8, 2])
+        .html(&format!("{}?nl=1-1", page), &image_page(fine, "2-2"))
+        .image(fine, vec![1u8, 2, 3]));
+
+    let explorer = |hops: u32| Explorer::builder()
+        .transport(transport.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .retry(RetryPolicy::never())
+        .failover(hops)
+        .build()
+        .unwrap();
+
+    let article = explorer(1).article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.load_image(0).await.unwrap(), [1, 2, 3]);
+    assert_eq!(transport.requests().last().unwrap().uri().to_string(), fine);
+
+    // no other server to go to
+    let article = explorer(0).article_from_path(String::from(URL)).await.unwrap();
+    let res = article.load_image(0).await;
+    assert!(matches!(res, Err(Error::Integrity { ref actual, .. }) if actual.starts_with("0ca623e285")));
+
+    // a stream fails at its end, when the whole image is there
+    let mut stream = article.image_stream(0).await.unwrap();
+    assert_eq!(&stream.next().await.unwrap().unwrap()[..], [1, 2]);
+    assert!(matches!(stream.next().await, Some(Err(Error::Integrity { .. }))));
+
+    // and a download leaves nothing behind to be resumed
+    let dir = temp_dir("integrity");
+    let path = dir.join("001.jpg");
+    let res = article.download_image(0, &path).await;
+    assert!(matches!(res, Err(Error::Integrity { .. })));
+    assert!(!path.exists());
+    assert!(!dir.join("001.jpg.part").exists());
+
+    // unless there's another server to go to
+    let article = explorer(1).article_from_path(String::from(URL)).await.unwrap();
+    assert_eq!(article.download_image(0, &path).await.unwrap(), 3);
+    assert_eq!(std::fs::read(&path).unwrap(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn account_status() {
+    use std::sync::Arc;
+    use crate::Error;
+
+    let transport = |home: &str| Arc::new(MemoryTransport::new()
+        .html("https://e-hentai.org/home.php", home)
+        .html("https://e-hentai.org/exchange.php?t=gp", EXCHANGE)
+        .html("https://e-hentai.org/hathperks.php", HATH_PERKS));
+
+    let explorer = |transport: Arc<MemoryTransport>, logged_in: bool| {
+        let builder = Explorer::builder()
+            .transport(transport)
+            .rate_limits(RateLimit::unlimited(), RateLimit::unlimited());
+
+        let builder = if logged_in { builder.cookies("1234", "abcd") } else { builder };
+        builder.build().unwrap()
+    };
+
+    let status = explorer(transport(HOME), true).account_status().await.unwrap();
+    assert_eq!(status.image_limit.remaining(), 3766);
+    assert_eq!(status.image_limit.reset_cost, Some(123));
+    assert_eq!((status.gp, status.credits), (Some(12_345_000), Some(1_234_567)));
+    assert_eq!(status.hath_perks, ["Bronze Star", "Silver Star"]);
+
+    // guests only get to see the limit, without asking for the rest
+    let guest = transport(HOME);
+    let status = explorer(guest.clone(), false).account_status().await.unwrap();
+    assert_eq!(status.image_limit.used, 1234);
+    assert_eq!((status.gp, status.credits), (None, None));
+    assert!(status.hath_perks.is_empty());
+    assert_eq!(guest.requests().len(), 1);
+
+    let res = explorer(transport(HOME_GUEST), false).account_status().await;
+    assert!(matches!(res, Err(Error::Unauthorized)));
+
+    // exhentai doesn't have these pages
+    let sad = transport(HOME);
+    let ex = Explorer::builder()
+        .transport(sad.clone())
+        .rate_limits(RateLimit::unlimited(), RateLimit::unlimited())
+        .exhentai(true)
+        .cookies("1234", "abcd")
+        .build()
+        .unwrap();
+
+    assert_eq!(ex.account_status().await.unwrap().hath_perks.len(), 2);
+    assert!(sad.requests().iter().all(|req| req.uri().host() == Some("e-hentai.org")));
+
+    // a member's page which doesn't parse isn't hidden
+    let broken = Arc::new(MemoryTransport::new()
+        .html("https://e-hentai.org/home.php", HOME)
+        .html("https://e-hentai.org/exchange.php?t=gp", HOME)
+        .html("https://e-hentai.org/hathperks.php", HATH_PERKS));
+
+    let res = explorer(broken, true).account_status().await;
+    assert!(matches!(res, Err(Error::Parse(err)) if err.path() == Some("exchange.php?t=gp")));
+}
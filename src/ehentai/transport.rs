@@ -0,0 +1,125 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::pin::Pin;
+use std::future::Future;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+
+use crate::Error;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// what actually carries requests to servers and responses back;
+// the body of a response may still be arriving when it's returned
+pub trait Transport: Send + Sync {
+    fn send(&self, req: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>>;
+}
+
+#[derive(Clone)]
+struct Canned {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+// answers from a fixed set of responses, keyed by url;
+// anything else gets 404. handy for tests
+#[derive(Default)]
+pub struct MemoryTransport {
+    responses: Mutex<HashMap<String, Canned>>,
+    requests: Mutex<Vec<Request<()>>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn html(self, url: &str, html: &str) -> Self {
+        let headers = [("Content-Type", "text/html; charset=UTF-8")];
+        self.respond(url, StatusCode::OK, &headers, html.to_owned())
+    }
+
+    pub fn image(self, url: &str, data: impl Into<Bytes>) -> Self {
+        self.respond(url, StatusCode::OK, &[("Content-Type", "image/jpeg")], data)
+    }
+
+    // panics on invalid headers, as it's meant to be given literals
+    pub fn respond(
+        self,
+        url: &str,
+        status: StatusCode,
+        headers: &[(&str, &str)],
+        body: impl Into<Bytes>
+    ) -> Self {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap()
+            ))
+            .collect();
+
+        let canned = Canned {
+            status,
+            headers,
+            body: body.into(),
+        };
+
+        self.responses.lock().unwrap().insert(url.to_owned(), canned);
+        self
+    }
+
+    // the requests made so far, without their bodies
+    pub fn requests(&self) -> Vec<Request<()>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|req| {
+                let mut copy = Request::new(());
+                *copy.method_mut() = req.method().clone();
+                *copy.uri_mut() = req.uri().clone();
+                *copy.headers_mut() = req.headers().clone();
+                copy
+            })
+            .collect()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, req: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        let url = req.uri().to_string();
+        let canned = self.responses.lock().unwrap().get(&url).cloned();
+
+        let (parts, _) = req.into_parts();
+        self.requests.lock().unwrap().push(Request::from_parts(parts, ()));
+
+        let res = match canned {
+            Some(canned) => {
+                let mut res = Response::new(Body::from(canned.body));
+                *res.status_mut() = canned.status;
+
+                for (name, value) in canned.headers {
+                    res.headers_mut().append(name, value);
+                }
+
+                res
+            },
+            None => {
+                let mut res = Response::new(Body::from("not found"));
+                *res.status_mut() = StatusCode::NOT_FOUND;
+                res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+                res
+            },
+        };
+
+        Box::pin(async move { Ok(res) })
+    }
+}
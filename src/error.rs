@@ -0,0 +1,172 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::StatusCode;
+use hyper::http;
+
+use crate::ehentai::ParseError;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    // failed to connect, or the connection broke in the middle
+    Network(hyper::Error),
+    // the server took too long to answer
+    Timeout,
+    // failed to write downloaded data
+    Io(io::Error),
+    // the server answered with a non-successful status
+    Status(StatusCode),
+    // the page was fetched but didn't look like what we expected
+    Parse(ParseError),
+    // the gallery (or page) doesn't exist
+    NotFound,
+    // the gallery has been removed or expunged; carries the notice
+    Removed(String),
+    // the server asked us to slow down;
+    // carries how long to wait if it told us (with Retry-After)
+    RateLimited(Option<Duration>),
+    // our ip address is banned for a while;
+    // carries how long it lasts if the notice says so
+    Banned(Option<Duration>),
+    // the image quota of the ip address or the account is exhausted
+    QuotaExceeded,
+    // the original image can only be had by spending GP, which the
+    // account doesn't have enough of (or we weren't allowed to spend)
+    GpRequired,
+    // the image we got isn't the one we asked for (e.g. it was cut off);
+    // carries the beginning of SHA-1 it should have, and what it has
+    Integrity { expected: String, actual: String },
+    // the caller gave us something we can't use (e.g. malformed url)
+    InvalidInput(String),
+    // the site refused to show us anything, e.g. the blank page (once a
+    // "sad panda" image) exhentai gives to those without valid cookies
+    Unauthorized,
+    // the forums didn't accept the username or the password
+    WrongCredentials,
+    // the forums want a captcha solved before letting us log in,
+    // which we can't do; log in with a browser and use the cookies instead
+    CaptchaRequired,
+    // logging in failed for another reason; carries what the forums said
+    LoginFailed(String),
+    // the same request was made more than once at a time, and the one
+    // actually sent failed with this; every one of them gets it
+    Shared(Arc<Error>),
+}
+
+impl Error {
+    // the error itself, seeing through Error::Shared
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Shared(err) => err.inner(),
+            err => err,
+        }
+    }
+
+    // whether the same request may succeed if we just try again later
+    pub fn is_transient(&self) -> bool {
+        match self.inner() {
+            Error::Network(_) | Error::Timeout | Error::RateLimited(_) => true,
+            // another try (or another server) may give us the right one
+            Error::Integrity { .. } => true,
+            Error::Status(status) => status.is_server_error(),
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network(err) => write!(f, "Network error: {}", err),
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Status(status) => write!(f, "Server responded with {}", status),
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::NotFound => write!(f, "Requested page was not found"),
+            Error::Removed(reason) => write!(f, "Gallery is unavailable: {}", reason),
+            Error::RateLimited(Some(after)) => write!(
+                f, "Too many requests; retry after {} seconds",
+                after.as_secs()
+            ),
+            Error::RateLimited(None) => write!(f, "Too many requests"),
+            Error::Banned(Some(expiry)) => write!(
+                f, "IP address is temporarily banned for {} more seconds",
+                expiry.as_secs()
+            ),
+            Error::Banned(None) => write!(f, "IP address is temporarily banned"),
+            Error::QuotaExceeded => write!(f, "Image quota is exceeded"),
+            Error::GpRequired => write!(f, "Downloading the original image requires GP"),
+            Error::Integrity { expected, actual } => write!(
+                f, "Image is corrupted; expected SHA-1 {}..., got {}",
+                expected, actual
+            ),
+            Error::InvalidInput(what) => write!(f, "Invalid input: {}", what),
+            Error::Unauthorized => write!(f, "Not authorized to access the site"),
+            Error::WrongCredentials => write!(f, "Username or password is incorrect"),
+            Error::CaptchaRequired => write!(f, "Logging in requires a captcha"),
+            Error::LoginFailed(reason) => write!(f, "Failed to log in: {}", reason),
+            Error::Shared(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::Shared(err) => err.source(),
+            _ => None
+        }
+    }
+}
+
+// the only one holding the error gets it back as it was
+impl From<Arc<Error>> for Error {
+    fn from(err: Arc<Error>) -> Self {
+        Arc::try_unwrap(err).unwrap_or_else(Error::Shared)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Self {
+        Error::Network(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<http::uri::InvalidUri> for Error {
+    fn from(err: http::uri::InvalidUri) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
+impl From<http::header::InvalidHeaderValue> for Error {
+    fn from(err: http::header::InvalidHeaderValue) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(err: http::Error) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
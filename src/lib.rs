@@ -6,5 +6,20 @@ extern crate tokio;
 extern crate hyper;
 extern crate select;
 extern crate detour;
+extern crate hyper_rustls;
+extern crate httpdate;
+extern crate flate2;
+extern crate brotli_decompressor;
+extern crate encoding_rs;
+extern crate bytes;
+extern crate futures_util;
+extern crate rand;
+extern crate tokio_socks;
+extern crate base64;
+extern crate sha1;
+
+mod error;
 
 pub mod ehentai;
+
+pub use error::Error;